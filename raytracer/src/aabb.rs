@@ -23,6 +23,15 @@ impl Aabb {
         self.maximum
     }
 
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d: Vec3 = self.maximum - self.minimum;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
             let invd = 1.0 / r.dir[a];
@@ -1,3 +1,4 @@
+use crate::random::{self, SampleRng};
 use crate::{
     aabb::Aabb,
     hittable::*,
@@ -5,6 +6,7 @@ use crate::{
     vec3::{Point3, Vec3},
     Ray,
 };
+use rand::Rng;
 use std::sync::Arc;
 
 pub struct XyRect {
@@ -37,7 +39,7 @@ impl XyRect {
 }
 
 impl Hittable for XyRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.orig.z()) / r.dir.z();
         if t < t_min || t > t_max {
             return None;
@@ -68,6 +70,25 @@ impl Hittable for XyRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(rec) = self.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+            let area: f64 = (self.x1 - self.x0) * (self.y1 - self.y0);
+            let distance_squared: f64 = rec.t * rec.t * v.squared_length();
+            let cosine: f64 = (Vec3::dot(*v, rec.normal) / v.length()).abs();
+            distance_squared / (cosine * area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: &Point3, _time: f64) -> Vec3 {
+        let mut rng: SampleRng = random::rng();
+        let a: f64 = self.x0 + (self.x1 - self.x0) * rng.gen::<f64>();
+        let b: f64 = self.y0 + (self.y1 - self.y0) * rng.gen::<f64>();
+        let random_point: Point3 = Point3::new(a, b, self.k);
+        random_point - *o
+    }
 }
 
 pub struct YzRect {
@@ -100,7 +121,7 @@ impl YzRect {
 }
 
 impl Hittable for YzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.orig.x()) / r.dir.x();
         if t < t_min || t > t_max {
             return None;
@@ -131,6 +152,25 @@ impl Hittable for YzRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(rec) = self.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+            let area: f64 = (self.y1 - self.y0) * (self.z1 - self.z0);
+            let distance_squared: f64 = rec.t * rec.t * v.squared_length();
+            let cosine: f64 = (Vec3::dot(*v, rec.normal) / v.length()).abs();
+            distance_squared / (cosine * area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: &Point3, _time: f64) -> Vec3 {
+        let mut rng: SampleRng = random::rng();
+        let a: f64 = self.y0 + (self.y1 - self.y0) * rng.gen::<f64>();
+        let b: f64 = self.z0 + (self.z1 - self.z0) * rng.gen::<f64>();
+        let random_point: Point3 = Point3::new(self.k, a, b);
+        random_point - *o
+    }
 }
 
 pub struct XzRect {
@@ -163,7 +203,7 @@ impl XzRect {
 }
 
 impl Hittable for XzRect {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - r.orig.y()) / r.dir.y();
         if t < t_min || t > t_max {
             return None;
@@ -194,4 +234,23 @@ impl Hittable for XzRect {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some(rec) = self.hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY) {
+            let area: f64 = (self.x1 - self.x0) * (self.z1 - self.z0);
+            let distance_squared: f64 = rec.t * rec.t * v.squared_length();
+            let cosine: f64 = (Vec3::dot(*v, rec.normal) / v.length()).abs();
+            distance_squared / (cosine * area)
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: &Point3, _time: f64) -> Vec3 {
+        let mut rng: SampleRng = random::rng();
+        let a: f64 = self.x0 + (self.x1 - self.x0) * rng.gen::<f64>();
+        let b: f64 = self.z0 + (self.z1 - self.z0) * rng.gen::<f64>();
+        let random_point: Point3 = Point3::new(a, self.k, b);
+        random_point - *o
+    }
 }
@@ -0,0 +1,164 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::vec3::Vec3;
+
+/// Values that can be blended between two keyframes, from `self` at
+/// `s` = 0 to `other` at 1.
+pub trait Lerp: Copy {
+    fn lerp(&self, other: &Self, s: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(&self, other: &f64, s: f64) -> f64 {
+        self + s * (other - self)
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Vec3, s: f64) -> Vec3 {
+        *self + s * (*other - *self)
+    }
+}
+
+/// The value of something at a few points in time, blended in between and
+/// held before the first key and after the last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframes<T> {
+    keys: Vec<(f64, T)>,
+}
+
+impl<T: Lerp> Keyframes<T> {
+    /// Keys as `(time, value)` in any order, or None if there are none.
+    pub fn new(mut keys: Vec<(f64, T)>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Self { keys })
+    }
+
+    /// The keys ordered by time.
+    pub fn keys(&self) -> &[(f64, T)] {
+        &self.keys
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let next: usize = self.keys.partition_point(|&(t, _)| t <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (t0, a) = &self.keys[next - 1];
+        let (t1, b) = &self.keys[next];
+        a.lerp(b, (time - t0) / (t1 - t0))
+    }
+}
+
+/// How the frames of an image sequence are timed. Frame `n` is at time
+/// `n / fps` in seconds, which is what keyframe times count in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Animation {
+    pub fps: f64,
+    pub first_frame: usize,
+    pub last_frame: usize,
+    /// How long the shutter stays open in degrees of a frame, as on a
+    /// film camera's rotary shutter: 360 blurs across the whole frame, 0
+    /// freezes it.
+    pub shutter_angle: f64,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            fps: 24.0,
+            first_frame: 0,
+            last_frame: 0,
+            shutter_angle: 180.0,
+        }
+    }
+}
+
+impl Animation {
+    pub fn frames(&self) -> RangeInclusive<usize> {
+        self.first_frame..=self.last_frame
+    }
+
+    pub fn time(&self, frame: usize) -> f64 {
+        frame as f64 / self.fps
+    }
+
+    /// When the shutter opens and closes for `frame`, the `time0` and
+    /// `time1` of its camera. It opens at the time of the frame, so motion
+    /// blur reaches towards the next one.
+    pub fn shutter(&self, frame: usize) -> (f64, f64) {
+        let open: f64 = self.time(frame);
+        (open, open + self.shutter_angle / 360.0 / self.fps)
+    }
+}
+
+/// A range of frames given as `FIRST-LAST`, or a single frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid frame \"{}\"", n))
+        };
+        let (first, last) = match s.split_once('-') {
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(s)?, number(s)?),
+        };
+        if last < first {
+            return Err(format!("frame range \"{}\" ends before it starts", s));
+        }
+        Ok(FrameRange { first, last })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyframes() {
+        let keys = Keyframes::new(vec![(2.0, 10.0), (0.0, 0.0), (3.0, 10.0)]).unwrap();
+        assert_eq!(keys.keys()[0], (0.0, 0.0));
+        assert_eq!(keys.at(-1.0), 0.0);
+        assert_eq!(keys.at(1.0), 5.0);
+        assert_eq!(keys.at(2.5), 10.0);
+        assert_eq!(keys.at(9.0), 10.0);
+        assert!(Keyframes::<f64>::new(vec![]).is_none());
+        // A jump where two keys share a time
+        let step = Keyframes::new(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]).unwrap();
+        assert_eq!(step.at(0.5), 0.0);
+        assert_eq!(step.at(1.0), 1.0);
+    }
+
+    #[test]
+    fn test_shutter() {
+        let animation = Animation {
+            fps: 25.0,
+            first_frame: 1,
+            last_frame: 50,
+            shutter_angle: 180.0,
+        };
+        assert_eq!(animation.frames().count(), 50);
+        let (open, close) = animation.shutter(10);
+        assert!((open - 0.4).abs() < 1e-12 && (close - 0.42).abs() < 1e-12);
+        assert_eq!("3-7".parse(), Ok(FrameRange { first: 3, last: 7 }));
+        assert_eq!("5".parse(), Ok(FrameRange { first: 5, last: 5 }));
+        assert!("7-3".parse::<FrameRange>().is_err());
+        assert!("a-b".parse::<FrameRange>().is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgb, RgbImage};
+
+use crate::color::quantize;
+use crate::framebuffer::Framebuffer;
+use crate::output::sample_heatmap;
+use crate::random::mix;
+use crate::tonemap::ToneMapper;
+use crate::vec3::{Color, Vec3};
+
+/// An arbitrary output variable: a pass written next to the image, with
+/// something other than the radiance in every pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Ray distance `t` to the first hit, 0 where nothing was hit.
+    Depth,
+    /// World space normal at the first hit, facing the camera.
+    Normal,
+    Albedo,
+    /// Texture coordinates of the first hit in red and green.
+    Uv,
+    /// Number of the scene object first hit, 0 for the background.
+    ObjectId,
+    /// Number of the material first hit, given by name order when a scene
+    /// file is loaded. Built-in scenes and MTL files leave theirs at 0.
+    MaterialId,
+    /// Light that reached the camera after one bounce.
+    Direct,
+    /// Light that reached the camera after two bounces or more.
+    Indirect,
+    /// Lights and background seen directly.
+    Emission,
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Uv,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::SampleCount,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object",
+            Aov::MaterialId => "material",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::SampleCount => "samples",
+        }
+    }
+
+    /// Whether the pass comes from what camera rays hit first, which the
+    /// renderer only records with `Renderer::features` on.
+    pub fn needs_first_hit(&self) -> bool {
+        matches!(
+            self,
+            Aov::Depth | Aov::Normal | Aov::Albedo | Aov::Uv | Aov::ObjectId | Aov::MaterialId
+        )
+    }
+
+    /// The linear values of the pass, rows from the top.
+    pub fn values(&self, fb: &Framebuffer) -> Vec<Color> {
+        let pixels: usize = fb.width * fb.height;
+        let gray = |v: f64| Color::new(v, v, v);
+        let per_hit = |i: usize, sum: f64| sum / (fb.hits[i].max(1) as f64);
+        let per_weight = |i: usize, sum: Color| {
+            if fb.weights[i] > 0.0 {
+                sum / fb.weights[i]
+            } else {
+                Color::new(0.0, 0.0, 0.0)
+            }
+        };
+        match self {
+            Aov::Depth => (0..pixels)
+                .map(|i| gray(per_hit(i, fb.depths[i])))
+                .collect(),
+            Aov::Normal => fb
+                .normals
+                .iter()
+                .map(|&n| {
+                    if n.length() > 1e-6 {
+                        Vec3::unit_vector(n)
+                    } else {
+                        Vec3::new(0.0, 0.0, 0.0)
+                    }
+                })
+                .collect(),
+            Aov::Albedo => (0..pixels)
+                .map(|i| fb.albedo[i] / (fb.samples[i].max(1) as f64))
+                .collect(),
+            Aov::Uv => (0..pixels)
+                .map(|i| {
+                    let (u, v) = fb.uvs[i];
+                    Color::new(per_hit(i, u), per_hit(i, v), 0.0)
+                })
+                .collect(),
+            Aov::ObjectId => fb.object_ids.iter().map(|&id| gray(id as f64)).collect(),
+            Aov::MaterialId => fb.material_ids.iter().map(|&id| gray(id as f64)).collect(),
+            Aov::Direct => (0..pixels).map(|i| per_weight(i, fb.direct[i])).collect(),
+            Aov::Indirect => (0..pixels).map(|i| per_weight(i, fb.indirect[i])).collect(),
+            Aov::Emission => (0..pixels).map(|i| per_weight(i, fb.emission[i])).collect(),
+            Aov::SampleCount => fb.samples.iter().map(|&n| gray(n as f64)).collect(),
+        }
+    }
+
+    /// An 8-bit picture of the pass. Radiance and albedo go through
+    /// `tone_mapper` like the image, IDs get a color each, and depth runs
+    /// from white at the nearest hit to dark gray at the farthest.
+    pub fn display(&self, fb: &Framebuffer, tone_mapper: &ToneMapper) -> RgbImage {
+        let values: Vec<Color> = self.values(fb);
+        let pixels: Vec<[u8; 3]> = match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect | Aov::Emission => {
+                values.iter().map(|&c| tone_mapper.to_rgb8(c)).collect()
+            }
+            Aov::Depth => {
+                let hit = |i: usize| fb.hits[i] > 0;
+                let depths = (0..values.len()).filter(|&i| hit(i)).map(|i| values[i].x());
+                let (near, far) = depths.fold((f64::INFINITY, 0.0_f64), |(lo, hi), d| {
+                    (lo.min(d), hi.max(d))
+                });
+                let range: f64 = (far - near).max(1e-9);
+                (0..values.len())
+                    .map(|i| {
+                        let v: f64 = if hit(i) {
+                            1.0 - 0.9 * (values[i].x() - near) / range
+                        } else {
+                            0.0
+                        };
+                        quantize(Color::new(v, v, v))
+                    })
+                    .collect()
+            }
+            Aov::Normal => values
+                .iter()
+                .map(|&n| {
+                    if n.length() > 0.0 {
+                        quantize(0.5 * (n + Vec3::new(1.0, 1.0, 1.0)))
+                    } else {
+                        [0, 0, 0]
+                    }
+                })
+                .collect(),
+            Aov::Uv => values.iter().map(|&c| quantize(c)).collect(),
+            Aov::ObjectId | Aov::MaterialId => {
+                values.iter().map(|&c| id_color(c.x() as u64)).collect()
+            }
+            Aov::SampleCount => return sample_heatmap(fb),
+        };
+        ImageBuffer::from_fn(fb.width as u32, fb.height as u32, |x, y| {
+            Rgb(pixels[y as usize * fb.width + x as usize])
+        })
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .into_iter()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| format!("unknown pass \"{}\"", s))
+    }
+}
+
+/// Parses a comma separated list of passes, or `all`.
+pub fn parse_list(s: &str) -> Result<Vec<Aov>, String> {
+    if s == "all" {
+        return Ok(Aov::ALL.to_vec());
+    }
+    let mut aovs: Vec<Aov> = vec![];
+    for name in s.split(',') {
+        let aov: Aov = name.trim().parse()?;
+        if !aovs.contains(&aov) {
+            aovs.push(aov);
+        }
+    }
+    Ok(aovs)
+}
+
+/// A bright color of its own for every ID, black for 0.
+fn id_color(id: u64) -> [u8; 3] {
+    if id == 0 {
+        return [0, 0, 0];
+    }
+    let bits: u64 = mix(id);
+    [0, 8, 16].map(|shift| 64 + ((bits >> shift) & 0xff) as u8 % 192)
+}
+
+/// What a camera ray sees first, recorded for the passes and the denoiser.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FirstHit {
+    pub albedo: Color,
+    /// Zero for rays escaping to the background.
+    pub normal: Vec3,
+    /// Infinite for rays escaping to the background.
+    pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl FirstHit {
+    /// A ray escaping to a background of color `background`.
+    pub fn miss(background: Color) -> Self {
+        Self {
+            albedo: background,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            t: f64::INFINITY,
+            u: 0.0,
+            v: 0.0,
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::TileBuffer;
+    use crate::render::Lighting;
+    use crate::tile::Tile;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_list("depth, object,depth"),
+            Ok(vec![Aov::Depth, Aov::ObjectId])
+        );
+        assert_eq!(parse_list("all").unwrap().len(), 10);
+        assert!(parse_list("depth,zbuffer").is_err());
+        for aov in Aov::ALL {
+            assert_eq!(aov.name().parse(), Ok(aov));
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        let tile = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 3,
+            y1: 1,
+        };
+        let mut buffer = TileBuffer::new(tile);
+        let hit = FirstHit {
+            albedo: Color::new(0.5, 0.5, 0.5),
+            normal: Vec3::new(0.0, 2.0, 0.0),
+            t: 3.0,
+            u: 0.25,
+            v: 0.75,
+            object_id: 7,
+            material_id: 5,
+        };
+        for (x, t, material_id) in [(0, 3.0, 3), (0, 5.0, 5), (1, 1.0, 5)] {
+            buffer.add_hit(
+                x,
+                0,
+                &FirstHit {
+                    t,
+                    material_id,
+                    ..hit
+                },
+            );
+        }
+        buffer.add_hit(2, 0, &FirstHit::miss(Color::new(0.0, 0.0, 1.0)));
+        let lighting = Lighting {
+            emission: Color::new(0.0, 0.0, 0.0),
+            direct: Color::new(1.0, 0.0, 0.0),
+            indirect: Color::new(0.0, 0.5, 0.0),
+        };
+        buffer.count(0, 0, lighting.total());
+        buffer.splat(0.5, 0.5, &lighting, &crate::filter::Filter::default());
+        let mut fb = Framebuffer::new(3, 1);
+        fb.merge(&buffer);
+
+        let depth: Vec<f64> = Aov::Depth.values(&fb).iter().map(|c| c.x()).collect();
+        assert_eq!(depth, vec![4.0, 1.0, 0.0]);
+        assert_eq!(Aov::Normal.values(&fb)[0], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(Aov::Uv.values(&fb)[1], Color::new(0.25, 0.75, 0.0));
+        // The ID of the earliest sample to hit something
+        let ids: Vec<f64> = Aov::MaterialId.values(&fb).iter().map(|c| c.x()).collect();
+        assert_eq!(ids, vec![3.0, 5.0, 0.0]);
+        assert_eq!(Aov::ObjectId.values(&fb)[2], Color::new(0.0, 0.0, 0.0));
+        assert_eq!(Aov::Direct.values(&fb)[0], Color::new(1.0, 0.0, 0.0));
+        assert_eq!(fb.pixel(0, 0), Color::new(1.0, 0.5, 0.0));
+
+        let image = Aov::Depth.display(&fb, &ToneMapper::default());
+        assert_eq!(image.get_pixel(1, 0).0, [254, 254, 254]);
+        assert_eq!(image.get_pixel(2, 0).0, [0, 0, 0]);
+        assert_ne!(id_color(1), id_color(2));
+    }
+}
@@ -84,7 +84,12 @@ impl Hittable for Box_ {
         Some(Aabb::new(self.box_min, self.box_max))
     }
 
-    fn hit(&self, r: &crate::Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::HitRecord> {
+    fn hit(
+        &self,
+        r: &crate::Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<crate::hittable::HitRecord<'_>> {
         self.sides.hit(r, t_min, t_max)
     }
 }
@@ -2,9 +2,321 @@ use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 use crate::ray::Ray;
-use rand::Rng;
+use crate::vec3::Point3;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// Number of buckets the binned SAH builder sorts centroids into per axis.
+const SAH_BINS: usize = 12;
+/// Cost of visiting a node, relative to intersecting a primitive.
+const TRAVERSAL_COST: f64 = 0.125;
+const INTERSECTION_COST: f64 = 1.0;
+/// SAH leaves never hold more primitives than this.
+const MAX_LEAF_SIZE: usize = 4;
+/// From this depth on nodes are split at the median whatever the method, so
+/// a tree over badly skewed input adds at most a balanced tree's depth and
+/// stays within `MAX_TRAVERSAL_DEPTH`.
+const MAX_SAH_DEPTH: usize = 32;
+
+/// How the BVH builder partitions primitives at each node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Binned surface area heuristic over all three axes.
+    #[default]
+    Sah,
+    /// Sort along the widest axis and split the list in half.
+    Median,
+}
+
+impl FromStr for SplitMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sah" => Ok(SplitMethod::Sah),
+            "median" => Ok(SplitMethod::Median),
+            _ => Err(format!("unknown BVH split method \"{}\"", s)),
+        }
+    }
+}
+
+/// Shape of a built BVH, for diagnosing slow renders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BvhStats {
+    pub primitives: usize,
+    pub interior_nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of tracing a ray through the tree, in primitive
+    /// intersections, assuming rays hit nodes in proportion to their area.
+    pub sah_cost: f64,
+}
+
+impl Default for BvhStats {
+    fn default() -> Self {
+        Self {
+            primitives: 0,
+            interior_nodes: 0,
+            leaves: 0,
+            max_depth: 0,
+            min_leaf_size: usize::MAX,
+            max_leaf_size: 0,
+            sah_cost: 0.0,
+        }
+    }
+}
+
+impl BvhStats {
+    pub fn nodes(&self) -> usize {
+        self.interior_nodes + self.leaves
+    }
+
+    pub fn mean_leaf_size(&self) -> f64 {
+        self.primitives as f64 / self.leaves.max(1) as f64
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "BVH: {} primitives", self.primitives)?;
+        writeln!(
+            f,
+            "  nodes: {} ({} interior, {} leaves)",
+            self.nodes(),
+            self.interior_nodes,
+            self.leaves
+        )?;
+        writeln!(f, "  depth: {}", self.max_depth)?;
+        writeln!(
+            f,
+            "  leaf size: {} to {}, {:.2} on average",
+            self.min_leaf_size.min(self.max_leaf_size),
+            self.max_leaf_size,
+            self.mean_leaf_size()
+        )?;
+        write!(f, "  SAH cost: {:.3}", self.sah_cost)
+    }
+}
+
+/// A primitive waiting to be placed in the tree.
+struct Primitive {
+    object: Arc<dyn Hittable + Send + Sync>,
+    box_: Aabb,
+    centroid: Point3,
+}
+
+/// The tree as it comes out of the builder, before it is turned into
+/// `BVHNode`s or flattened.
+pub(crate) enum BuildNode {
+    Leaf {
+        box_: Aabb,
+        objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    },
+    Interior {
+        box_: Aabb,
+        axis: i32,
+        children: Box<[BuildNode; 2]>,
+    },
+}
+
+fn union(prims: &[Primitive]) -> Aabb {
+    prims[1..].iter().fold(prims[0].box_.clone(), |acc, p| {
+        Aabb::surrounding_box(acc, p.box_.clone())
+    })
+}
+
+fn centroid_bounds(prims: &[Primitive]) -> Aabb {
+    prims[1..]
+        .iter()
+        .fold(Aabb::new(prims[0].centroid, prims[0].centroid), |acc, p| {
+            Aabb::surrounding_box(acc, Aabb::new(p.centroid, p.centroid))
+        })
+}
+
+fn widest_axis(bounds: &Aabb) -> i32 {
+    let d = bounds.max() - bounds.min();
+    if d.x() >= d.y() && d.x() >= d.z() {
+        0
+    } else if d.y() >= d.z() {
+        1
+    } else {
+        2
+    }
+}
+
+/// Finds the cheapest binned SAH split, as `(cost, axis, bin)` where
+/// primitives in bins `0..bin` go left.
+fn best_sah_split(prims: &[Primitive], box_: &Aabb, centroids: &Aabb) -> Option<(f64, i32, usize)> {
+    let area: f64 = box_.surface_area();
+    let mut best: Option<(f64, i32, usize)> = None;
+    for axis in 0..3 {
+        let lo: f64 = centroids.min()[axis];
+        let extent: f64 = centroids.max()[axis] - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+        let mut counts: [usize; SAH_BINS] = [0; SAH_BINS];
+        let mut boxes: Vec<Option<Aabb>> = vec![None; SAH_BINS];
+        for p in prims {
+            let b: usize = bin_of(p.centroid[axis], lo, extent);
+            counts[b] += 1;
+            boxes[b] = Some(match boxes[b].take() {
+                Some(bb) => Aabb::surrounding_box(bb, p.box_.clone()),
+                None => p.box_.clone(),
+            });
+        }
+        // Sweep from the right to get the area and count right of each split
+        let mut right_area: [f64; SAH_BINS] = [0.0; SAH_BINS];
+        let mut right_count: [usize; SAH_BINS] = [0; SAH_BINS];
+        let mut acc: Option<Aabb> = None;
+        let mut n: usize = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc = merge(acc, &boxes[b]);
+            n += counts[b];
+            right_area[b] = acc.as_ref().map_or(0.0, Aabb::surface_area);
+            right_count[b] = n;
+        }
+        let mut acc: Option<Aabb> = None;
+        let mut n: usize = 0;
+        for b in 1..SAH_BINS {
+            acc = merge(acc, &boxes[b - 1]);
+            n += counts[b - 1];
+            if n == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let left_area: f64 = acc.as_ref().map_or(0.0, Aabb::surface_area);
+            let cost: f64 = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (n as f64 * left_area + right_count[b] as f64 * right_area[b])
+                    / area.max(f64::MIN_POSITIVE);
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+    best
+}
+
+fn bin_of(c: f64, lo: f64, extent: f64) -> usize {
+    (((c - lo) / extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+fn merge(acc: Option<Aabb>, b: &Option<Aabb>) -> Option<Aabb> {
+    match (acc, b) {
+        (Some(a), Some(b)) => Some(Aabb::surrounding_box(a, b.clone())),
+        (a, None) => a,
+        (None, Some(b)) => Some(b.clone()),
+    }
+}
+
+fn make_leaf(prims: Vec<Primitive>, box_: Aabb, depth: usize, stats: &mut BvhStats) -> BuildNode {
+    let n: usize = prims.len();
+    stats.leaves += 1;
+    stats.max_depth = stats.max_depth.max(depth);
+    stats.min_leaf_size = stats.min_leaf_size.min(n);
+    stats.max_leaf_size = stats.max_leaf_size.max(n);
+    stats.sah_cost += box_.surface_area() * n as f64 * INTERSECTION_COST;
+    BuildNode::Leaf {
+        box_,
+        objects: prims.into_iter().map(|p| p.object).collect(),
+    }
+}
+
+fn build_recursive(
+    mut prims: Vec<Primitive>,
+    method: SplitMethod,
+    depth: usize,
+    stats: &mut BvhStats,
+) -> BuildNode {
+    let box_: Aabb = union(&prims);
+    let n: usize = prims.len();
+    if n == 1 {
+        return make_leaf(prims, box_, depth, stats);
+    }
+    let centroids: Aabb = centroid_bounds(&prims);
+
+    let method: SplitMethod = if depth >= MAX_SAH_DEPTH {
+        SplitMethod::Median
+    } else {
+        method
+    };
+    let (axis, right): (i32, Vec<Primitive>) = match method {
+        SplitMethod::Median => {
+            let axis: i32 = widest_axis(&centroids);
+            prims.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            (axis, prims.split_off(n / 2))
+        }
+        SplitMethod::Sah => match best_sah_split(&prims, &box_, &centroids) {
+            Some((cost, _, _)) if n <= MAX_LEAF_SIZE && INTERSECTION_COST * n as f64 <= cost => {
+                return make_leaf(prims, box_, depth, stats);
+            }
+            Some((_, axis, bin)) => {
+                let lo: f64 = centroids.min()[axis];
+                let extent: f64 = centroids.max()[axis] - lo;
+                let (left, right): (Vec<Primitive>, Vec<Primitive>) = prims
+                    .into_iter()
+                    .partition(|p| bin_of(p.centroid[axis], lo, extent) < bin);
+                prims = left;
+                (axis, right)
+            }
+            // All centroids coincide, so no split can separate them
+            None if n <= MAX_LEAF_SIZE => return make_leaf(prims, box_, depth, stats),
+            None => (widest_axis(&centroids), prims.split_off(n / 2)),
+        },
+    };
+
+    stats.interior_nodes += 1;
+    stats.sah_cost += box_.surface_area() * TRAVERSAL_COST;
+    let left: BuildNode = build_recursive(prims, method, depth + 1, stats);
+    let right: BuildNode = build_recursive(right, method, depth + 1, stats);
+    BuildNode::Interior {
+        box_,
+        axis,
+        children: Box::new([left, right]),
+    }
+}
+
+/// Builds the tree over `objects`, which must all have bounding boxes.
+pub(crate) fn build_tree(
+    objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    time0: f64,
+    time1: f64,
+    method: SplitMethod,
+) -> (BuildNode, BvhStats) {
+    assert!(!objects.is_empty(), "cannot build a BVH without objects");
+    let prims: Vec<Primitive> = objects
+        .into_iter()
+        .map(|object| {
+            let box_: Aabb = object
+                .bounding_box(time0, time1)
+                .expect("no bounding box in BVH node constructor");
+            let centroid: Point3 = box_.centroid();
+            Primitive {
+                object,
+                box_,
+                centroid,
+            }
+        })
+        .collect();
+    let mut stats = BvhStats {
+        primitives: prims.len(),
+        ..BvhStats::default()
+    };
+    let root: BuildNode = build_recursive(prims, method, 1, &mut stats);
+    let root_area: f64 = match &root {
+        BuildNode::Leaf { box_, .. } | BuildNode::Interior { box_, .. } => box_.surface_area(),
+    };
+    stats.sah_cost = if root_area > 0.0 {
+        stats.sah_cost / root_area
+    } else {
+        stats.primitives as f64 * INTERSECTION_COST
+    };
+    (root, stats)
+}
+
 pub struct BVHNode {
     left: Arc<dyn Hittable + Send + Sync>,
     right: Arc<dyn Hittable + Send + Sync>,
@@ -25,38 +337,40 @@ impl BVHNode {
         time0: f64,
         time1: f64,
     ) -> Arc<dyn Hittable + Send + Sync> {
-        let axis: i32 = rand::thread_rng().gen_range(0..3);
-        let mut objects = src_objects;
-
-        objects.sort_by(|a, b| {
-            a.bounding_box(time0, time1).unwrap().min()[axis]
-                .partial_cmp(&b.bounding_box(time0, time1).unwrap().min()[axis])
-                .unwrap()
-        }); //基于https://github.com/JolyneFr/RayTracing/blob/master/src/bvh.rs
-
-        let mut left = objects[0].clone();
-        let mut right = objects[0].clone();
-        let object_span: usize = objects.len();
-        match object_span {
-            1 => {}
-            2 => right = objects[1].clone(),
-            _ => {
-                let mut l: Vec<Arc<dyn Hittable + Send + Sync>> = objects;
-                let r: Vec<Arc<dyn Hittable + Send + Sync>> = l.split_off(l.len() / 2);
-                left = BVHNode::build(l, time0, time1);
-                right = BVHNode::build(r, time0, time1);
+        BVHNode::build_with(src_objects, time0, time1, SplitMethod::Sah).0
+    }
+
+    /// Builds a BVH with the given split method and reports its statistics.
+    pub fn build_with(
+        src_objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+        time0: f64,
+        time1: f64,
+        method: SplitMethod,
+    ) -> (Arc<dyn Hittable + Send + Sync>, BvhStats) {
+        let (root, stats) = build_tree(src_objects, time0, time1, method);
+        (BVHNode::from_build(root), stats)
+    }
+
+    fn from_build(node: BuildNode) -> Arc<dyn Hittable + Send + Sync> {
+        match node {
+            BuildNode::Leaf { mut objects, .. } if objects.len() == 1 => objects.pop().unwrap(),
+            BuildNode::Leaf { objects, .. } => Arc::new(HittableList {
+                hittable_list: objects,
+            }),
+            BuildNode::Interior { box_, children, .. } => {
+                let [left, right] = *children;
+                Arc::new(Self {
+                    left: BVHNode::from_build(left),
+                    right: BVHNode::from_build(right),
+                    box_,
+                })
             }
-        };
-        let box_: Aabb = Aabb::surrounding_box(
-            left.bounding_box(time0, time1).unwrap(),
-            right.bounding_box(time0, time1).unwrap(),
-        );
-        Arc::new(Self { left, right, box_ })
+        }
     }
 }
 
 impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         if self.box_.hit(r, t_min, t_max) {
             let hit_left: Option<HitRecord<'_>> = self.left.hit(r, t_min, t_max);
             let hit_right: Option<HitRecord<'_>> = self.right.hit(r, t_min, t_max);
@@ -82,3 +396,219 @@ impl Hittable for BVHNode {
         Some(output_box)
     }
 }
+
+/// Traversal stack size of `LinearBVH`; trees deeper than this are rejected.
+const MAX_TRAVERSAL_DEPTH: usize = 64;
+
+/// A node of a `LinearBVH`. Leaves hold `count > 0` primitives starting at
+/// `offset`; interior nodes have their first child right after them and the
+/// second one at `offset`.
+struct LinearNode {
+    box_: Aabb,
+    offset: usize,
+    count: usize,
+    axis: i32,
+}
+
+/// A BVH flattened into an array in depth-first order, traversed without
+/// recursion and visiting the nearer child first.
+pub struct LinearBVH {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl LinearBVH {
+    pub fn new(list: HittableList, time0: f64, time1: f64) -> Self {
+        LinearBVH::build_with(list.hittable_list, time0, time1, SplitMethod::Sah).0
+    }
+
+    pub fn build_with(
+        src_objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+        time0: f64,
+        time1: f64,
+        method: SplitMethod,
+    ) -> (Self, BvhStats) {
+        let (root, stats) = build_tree(src_objects, time0, time1, method);
+        assert!(
+            stats.max_depth <= MAX_TRAVERSAL_DEPTH,
+            "BVH is {} levels deep, at most {} are supported",
+            stats.max_depth,
+            MAX_TRAVERSAL_DEPTH
+        );
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(stats.nodes()),
+            primitives: Vec::with_capacity(stats.primitives),
+        };
+        bvh.flatten(root);
+        (bvh, stats)
+    }
+
+    fn flatten(&mut self, node: BuildNode) {
+        match node {
+            BuildNode::Leaf { box_, objects } => {
+                self.nodes.push(LinearNode {
+                    box_,
+                    offset: self.primitives.len(),
+                    count: objects.len(),
+                    axis: 0,
+                });
+                self.primitives.extend(objects);
+            }
+            BuildNode::Interior {
+                box_,
+                axis,
+                children,
+            } => {
+                let index: usize = self.nodes.len();
+                self.nodes.push(LinearNode {
+                    box_,
+                    offset: 0,
+                    count: 0,
+                    axis,
+                });
+                let [first, second] = *children;
+                self.flatten(first);
+                self.nodes[index].offset = self.nodes.len();
+                self.flatten(second);
+            }
+        }
+    }
+}
+
+impl Hittable for LinearBVH {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest_so_far: f64 = t_max;
+        let mut hit_anything: Option<HitRecord<'_>> = None;
+        let mut stack: [usize; MAX_TRAVERSAL_DEPTH] = [0; MAX_TRAVERSAL_DEPTH];
+        let mut stack_len: usize = 0;
+        let mut current: usize = 0;
+        loop {
+            let node: &LinearNode = &self.nodes[current];
+            if node.box_.hit(r, t_min, closest_so_far) {
+                if node.count > 0 {
+                    for object in &self.primitives[node.offset..node.offset + node.count] {
+                        if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            hit_anything = Some(rec);
+                        }
+                    }
+                } else {
+                    // Visit the child on the side the ray comes from first, so
+                    // a close hit lets us skip the other child's box
+                    let (near, far) = if r.dir[node.axis] < 0.0 {
+                        (node.offset, current + 1)
+                    } else {
+                        (current + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        Some(self.nodes[0].box_.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Color, Vec3};
+
+    fn spheres() -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut objects: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let center = Point3::new(i as f64, ((i * 7 + j * 3) % 5) as f64, j as f64);
+                objects.push(Arc::new(Sphere::new(center, 0.3, mat.clone())));
+            }
+        }
+        objects
+    }
+
+    #[test]
+    fn test_split_methods_agree() {
+        let (sah, sah_stats) = BVHNode::build_with(spheres(), 0.0, 1.0, SplitMethod::Sah);
+        let (median, median_stats) = BVHNode::build_with(spheres(), 0.0, 1.0, SplitMethod::Median);
+        assert_eq!(sah_stats.primitives, 400);
+        assert_eq!(median_stats.leaves, 400);
+        assert!(sah_stats.max_leaf_size <= MAX_LEAF_SIZE);
+        assert!(sah_stats.sah_cost <= median_stats.sah_cost);
+
+        for k in 0..100 {
+            let origin = Point3::new(-5.0, 2.0 + (k % 3) as f64, -5.0);
+            let dir = Vec3::new(1.0 + (k as f64 * 0.37) % 1.0, -0.1, 1.0 + k as f64 * 0.01);
+            let r = Ray::new(origin, dir, 0.0);
+            let a = sah.hit(&r, 0.001, f64::INFINITY).map(|h| h.t);
+            let b = median.hit(&r, 0.001, f64::INFINITY).map(|h| h.t);
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_linear_bvh_matches_tree() {
+        let (tree, _) = BVHNode::build_with(spheres(), 0.0, 1.0, SplitMethod::Sah);
+        let (linear, stats) = LinearBVH::build_with(spheres(), 0.0, 1.0, SplitMethod::Sah);
+        assert_eq!(linear.nodes.len(), stats.nodes());
+        assert_eq!(linear.primitives.len(), 400);
+
+        for k in 0..200 {
+            let origin = Point3::new(25.0 - (k % 7) as f64 * 5.0, 6.0, -3.0 + (k % 11) as f64);
+            let dir = Vec3::new(
+                -1.0 + (k as f64 * 0.13) % 2.0,
+                -0.4,
+                0.3 + (k as f64 * 0.07) % 1.0,
+            );
+            let r = Ray::new(origin, dir, 0.0);
+            let a = tree.hit(&r, 0.001, f64::INFINITY).map(|h| (h.t, h.p));
+            let b = linear.hit(&r, 0.001, f64::INFINITY).map(|h| (h.t, h.p));
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_skewed_input_stays_shallow() {
+        // Every SAH split peels off the farthest sphere
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let objects = || {
+            (0..200)
+                .map(|i| {
+                    let center = Point3::new(3.0_f64.powi(i), 0.0, 0.0);
+                    Arc::new(Sphere::new(center, 1.0, mat.clone()))
+                        as Arc<dyn Hittable + Send + Sync>
+                })
+                .collect::<Vec<_>>()
+        };
+        let (linear, stats) = LinearBVH::build_with(objects(), 0.0, 1.0, SplitMethod::Sah);
+        assert!(stats.max_depth <= MAX_TRAVERSAL_DEPTH);
+        assert_eq!(stats.primitives, 200);
+        let (tree, _) = BVHNode::build_with(objects(), 0.0, 1.0, SplitMethod::Sah);
+        for i in 0..10 {
+            let x: f64 = 3.0_f64.powi(i);
+            let r = Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+            let hit = linear.hit(&r, 0.001, f64::INFINITY).map(|h| h.t);
+            assert_eq!(hit, Some(4.0));
+            assert_eq!(hit, tree.hit(&r, 0.001, f64::INFINITY).map(|h| h.t));
+        }
+    }
+
+    #[test]
+    fn test_build_is_deterministic() {
+        let (_, a) = BVHNode::build_with(spheres(), 0.0, 1.0, SplitMethod::Sah);
+        let (_, b) = BVHNode::build_with(spheres(), 0.0, 1.0, SplitMethod::Sah);
+        assert_eq!(a, b);
+    }
+}
@@ -1,8 +1,8 @@
 // mod rtweekend;
 use crate::ray::Ray;
+use crate::sampler;
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
-use rand::Rng;
 
 #[derive(Clone, Debug, PartialEq, Copy)]
 
@@ -63,15 +63,27 @@ impl Camera {
         }
     }
 
+    /// Widens or narrows the view to a new aspect ratio, keeping the vertical field of view.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let center: Vec3 = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+        self.horizontal = aspect_ratio * self.vertical.length() * self.u;
+        self.lower_left_corner = center - self.horizontal / 2.0 - self.vertical / 2.0;
+    }
+
+    /// When the shutter opens and closes, the times rays are spread over.
+    pub fn shutter(&self) -> (f64, f64) {
+        (self.time0, self.time1)
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
         let rd: Vec3 = self.lens_radius * Vec3::random_in_unit_disk();
         let offset: Vec3 = self.u * rd.x() + self.v * rd.y();
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
+        let time: f64 = self.time0 + sampler::get_1d() * (self.time1 - self.time0);
 
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            rng.gen_range(self.time0..self.time1),
+            time,
         )
     }
 }
@@ -0,0 +1,257 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::render::Progress;
+use crate::vec3::{Color, Vec3};
+
+const MAGIC: &[u8; 8] = b"RTCKPT05";
+/// The magic number and six `u64` header fields.
+const HEADER_BYTES: u64 = 8 + 6 * 8;
+/// 24 `f64` and 4 `u32` values per pixel.
+const PIXEL_BYTES: u64 = 24 * 8 + 4 * 4;
+
+/// Everything needed to carry on with an interrupted render: the samples
+/// accumulated so far and what produced them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    /// Hash of the settings that change the image, see `settings_hash`.
+    pub settings_hash: u64,
+    /// Seed of the scene and sample generators.
+    pub seed: u64,
+    pub progress: Progress,
+    /// The samples so far. Its sample counts are also where the sample
+    /// sequence of every pixel continues.
+    pub framebuffer: Framebuffer,
+}
+
+/// FNV-1a of a description of the render settings and the scene. Unlike
+/// `DefaultHasher` it gives the same value in every build, so checkpoints
+/// stay usable.
+pub fn settings_hash(settings: &[u8]) -> u64 {
+    settings.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b: [u8; 8] = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(r)?))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b: [u8; 4] = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint next to `path` first and then moves it into
+    /// place, so being killed halfway never leaves a truncated file behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        let fb = &self.framebuffer;
+        for v in [
+            self.settings_hash,
+            self.seed,
+            self.progress.passes as u64,
+            self.progress.samples,
+            fb.width as u64,
+            fb.height as u64,
+        ] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        for i in 0..fb.sums.len() {
+            for v in [
+                fb.sums[i].x(),
+                fb.sums[i].y(),
+                fb.sums[i].z(),
+                fb.weights[i],
+                fb.luminance_sums[i],
+                fb.luminance_squares[i],
+                fb.emission[i].x(),
+                fb.emission[i].y(),
+                fb.emission[i].z(),
+                fb.direct[i].x(),
+                fb.direct[i].y(),
+                fb.direct[i].z(),
+                fb.indirect[i].x(),
+                fb.indirect[i].y(),
+                fb.indirect[i].z(),
+                fb.albedo[i].x(),
+                fb.albedo[i].y(),
+                fb.albedo[i].z(),
+                fb.normals[i].x(),
+                fb.normals[i].y(),
+                fb.normals[i].z(),
+                fb.depths[i],
+                fb.uvs[i].0,
+                fb.uvs[i].1,
+            ] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+            for v in [
+                fb.samples[i],
+                fb.hits[i],
+                fb.object_ids[i],
+                fb.material_ids[i],
+            ] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic: [u8; 8] = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a render checkpoint"));
+        }
+        let settings_hash: u64 = read_u64(&mut r)?;
+        let seed: u64 = read_u64(&mut r)?;
+        let passes: u64 = read_u64(&mut r)?;
+        let samples: u64 = read_u64(&mut r)?;
+        let width: u64 = read_u64(&mut r)?;
+        let height: u64 = read_u64(&mut r)?;
+        let pixels: u64 = width
+            .checked_mul(height)
+            .filter(|&n| n <= u32::MAX as u64)
+            .ok_or_else(|| invalid("image size is out of range"))?;
+        // Checked before allocating, so a corrupt size cannot ask for more
+        // memory than the file could fill
+        if r.get_ref().metadata()?.len() != HEADER_BYTES + pixels * PIXEL_BYTES {
+            return Err(invalid("file size does not match the image size"));
+        }
+
+        let mut fb = Framebuffer::new(width as usize, height as usize);
+        for i in 0..pixels as usize {
+            let x: f64 = read_f64(&mut r)?;
+            let y: f64 = read_f64(&mut r)?;
+            let z: f64 = read_f64(&mut r)?;
+            fb.sums[i] = Color::new(x, y, z);
+            fb.weights[i] = read_f64(&mut r)?;
+            fb.luminance_sums[i] = read_f64(&mut r)?;
+            fb.luminance_squares[i] = read_f64(&mut r)?;
+            fb.emission[i] = Color::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.direct[i] = Color::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.indirect[i] = Color::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.albedo[i] = Color::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.normals[i] = Vec3::new(read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.depths[i] = read_f64(&mut r)?;
+            fb.uvs[i] = (read_f64(&mut r)?, read_f64(&mut r)?);
+            fb.samples[i] = read_u32(&mut r)?;
+            fb.hits[i] = read_u32(&mut r)?;
+            fb.object_ids[i] = read_u32(&mut r)?;
+            fb.material_ids[i] = read_u32(&mut r)?;
+        }
+        if r.read(&mut [0])? != 0 {
+            return Err(invalid("trailing data after the image"));
+        }
+        Ok(Checkpoint {
+            settings_hash,
+            seed,
+            progress: Progress {
+                passes: passes as usize,
+                samples,
+            },
+            framebuffer: fb,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::FirstHit;
+    use crate::filter::Filter;
+    use crate::framebuffer::TileBuffer;
+    use crate::render::Lighting;
+    use crate::tile::Tile;
+
+    #[test]
+    fn test_round_trip() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        let mut buffer = TileBuffer::new(Tile {
+            x0: 1,
+            y0: 0,
+            x1: 3,
+            y1: 2,
+        });
+        buffer.add(2, 1, Color::new(0.1, 2.0, 30.0), 1.0);
+        buffer.add(2, 1, Color::new(0.3, 0.0, 0.5), 1.0);
+        let hit = FirstHit {
+            albedo: Color::new(0.5, 0.5, 0.25),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            t: 2.5,
+            u: 0.5,
+            v: 0.125,
+            object_id: 3,
+            material_id: 4,
+        };
+        buffer.add_hit(2, 1, &hit);
+        buffer.splat(
+            2.5,
+            1.5,
+            &Lighting {
+                emission: Color::new(0.0, 0.0, 1.0),
+                direct: Color::new(1.0, 0.0, 0.0),
+                indirect: Color::new(0.0, 1.0, 0.0),
+            },
+            &Filter::default(),
+        );
+        framebuffer.merge(&buffer);
+        let checkpoint = Checkpoint {
+            settings_hash: settings_hash(b"cornell_box 600x600"),
+            seed: 42,
+            progress: Progress {
+                passes: 1,
+                samples: 2,
+            },
+            framebuffer,
+        };
+
+        let dir = std::env::temp_dir().join(format!("raytracer-ckpt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("render.ckpt");
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+
+        fs::write(&path, b"RTCKPT04 but too short").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        // A huge image in the header of a short file
+        let mut header: Vec<u8> = MAGIC.to_vec();
+        for v in [0, 0, 0, 0, 60_000, 60_000] {
+            header.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+        fs::write(&path, &header).unwrap();
+        let err = Checkpoint::load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, b"something else entirely").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_settings_hash_is_stable() {
+        assert_eq!(settings_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(settings_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(
+            settings_hash(b"final 800x800"),
+            settings_hash(b"final 800x801")
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use raytracer::aov;
+use raytracer::output::OutputFormat;
+use raytracer::sampler::SamplerKind;
+use raytracer::{
+    Aov, Filter, FrameRange, Integrator, SplitMethod, TileOrder, ToneMap, ToneMapper, Transfer,
+};
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+SCENE is the name of a built-in scene or the path of a .toml scene file.
+Built-in scenes: random, two_spheres, two_perlin_spheres, earth,
+simple_light, cornell_box, cornell_smoke, final (default).
+
+Options:
+  -s, --scene <SCENE>    scene name or scene file
+  -W, --width <N>        image width in pixels
+  -H, --height <N>       image height in pixels
+      --spp <N>          samples per pixel
+      --pass-spp <N>     render progressively in passes of N samples per
+                         pixel, rewriting the output after each pass
+                         [default: 16 with --checkpoint]
+      --previews         also keep the image of every pass, numbered
+      --adaptive <ERR>   stop sampling pixels once the standard error of
+                         their luminance falls below ERR relative to it,
+                         and spend the samples on noisier ones
+      --min-spp <N>      samples every pixel takes when adaptive
+                         [default: 16]
+      --max-spp <N>      most samples one pixel takes when adaptive
+                         [default: 8 times --spp]
+      --heatmap <PATH>   also save an image of the samples per pixel
+      --denoise          filter the noise out of the image, guided by the
+                         albedo and normals where camera rays first hit
+      --aov <PASSES>     also save these passes as OUTPUT.PASS.EXT, comma
+                         separated: depth, normal, albedo, uv, object,
+                         material, direct, indirect, emission, samples,
+                         or all; materials are numbered for scene files
+                         only, not built-in scenes or MTL files
+      --checkpoint <PATH>
+                         save the render state to PATH after passes and on
+                         Ctrl-C, so it can be resumed
+      --checkpoint-every <SECS>
+                         seconds between checkpoints [default: 300]
+      --resume <PATH>    continue the render saved in a checkpoint
+      --frames <RANGE>   render frames FIRST-LAST, or one frame, of an
+                         animated scene file to OUTPUT with `%04d` in it
+                         replaced by the frame number [default: the
+                         frames of its [animation] table]
+      --shutter <DEG>    shutter angle in degrees of a frame, 360 blurs
+                         motion over the whole frame [default: 180]
+  -d, --depth <N>        maximum ray bounce depth
+  -i, --integrator <I>   recursive, mis (power heuristic) or mis-balance
+                         [default: recursive]
+      --filter <F>       pixel reconstruction filter: box, tent, gaussian,
+                         mitchell or lanczos, with an optional :RADIUS
+                         in pixels [default: box:0.5]
+      --sampler <S>      sample pattern: independent, stratified, halton
+                         or sobol [default: independent]
+      --bvh <METHOD>     BVH split method: sah or median [default: sah]
+      --bvh-stats        print statistics of the built BVH
+  -j, --threads <N>      number of render threads [default: number of cores]
+      --tile-size <N>    edge length of render tiles in pixels [default: 16]
+      --tile-order <O>   order tiles are rendered in: spiral or scanline
+                         [default: spiral]
+  -o, --output <PATH>    output image path [default: output/test.jpg]
+  -f, --format <FMT>     output format: jpeg, png, or exr, hdr and pfm for
+                         linear radiance [default: from extension]
+  -q, --quality <N>      JPEG quality from 0 to 100 [default: 60]
+  -e, --exposure <EV>    exposure adjustment in stops [default: 0]
+      --tonemap <OP>     tone mapping for 8-bit output: clamp, reinhard,
+                         reinhard-extended[:WHITE], hable or aces
+                         [default: clamp]
+      --transfer <TF>    encoding for 8-bit output: gamma2 or srgb
+                         [default: gamma2]
+      --seed <N>         seed for randomly generated scenes and sampling
+      --help             print this message
+";
+
+/// Render settings given on the command line. `None` keeps the scene default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Args {
+    pub scene: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<u64>,
+    pub pass_samples: Option<u64>,
+    pub previews: bool,
+    pub adaptive: Option<f64>,
+    pub min_samples: Option<u64>,
+    pub max_samples: Option<u64>,
+    pub heatmap: Option<String>,
+    pub denoise: bool,
+    pub aovs: Vec<Aov>,
+    pub checkpoint: Option<String>,
+    pub checkpoint_every: u64,
+    pub resume: Option<String>,
+    pub frames: Option<FrameRange>,
+    pub shutter_angle: Option<f64>,
+    pub max_depth: Option<i32>,
+    pub integrator: Integrator,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub bvh: SplitMethod,
+    pub bvh_stats: bool,
+    pub threads: Option<usize>,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub output: String,
+    pub format: Option<OutputFormat>,
+    pub quality: u8,
+    pub tone_mapper: ToneMapper,
+    pub seed: Option<u64>,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            width: None,
+            height: None,
+            samples_per_pixel: None,
+            pass_samples: None,
+            previews: false,
+            adaptive: None,
+            min_samples: None,
+            max_samples: None,
+            heatmap: None,
+            denoise: false,
+            aovs: vec![],
+            checkpoint: None,
+            checkpoint_every: 300,
+            resume: None,
+            frames: None,
+            shutter_angle: None,
+            max_depth: None,
+            integrator: Integrator::Recursive,
+            sampler: SamplerKind::Independent,
+            filter: Filter::default(),
+            bvh: SplitMethod::Sah,
+            bvh_stats: false,
+            threads: None,
+            tile_size: 16,
+            tile_order: TileOrder::Spiral,
+            output: String::from("output/test.jpg"),
+            format: None,
+            quality: 60,
+            tone_mapper: ToneMapper::default(),
+            seed: None,
+            help: false,
+        }
+    }
+}
+
+fn value<T: std::str::FromStr>(
+    flag: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String> {
+    let v = args
+        .next()
+        .ok_or_else(|| format!("missing value for `{}`", flag))?;
+    v.parse()
+        .map_err(|_| format!("invalid value \"{}\" for `{}`", v, flag))
+}
+
+impl Args {
+    /// Parses the arguments following the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-s" | "--scene" => parsed.scene = Some(value(&arg, &mut args)?),
+                "-W" | "--width" => parsed.width = Some(value(&arg, &mut args)?),
+                "-H" | "--height" => parsed.height = Some(value(&arg, &mut args)?),
+                "--spp" => parsed.samples_per_pixel = Some(value(&arg, &mut args)?),
+                "--pass-spp" => parsed.pass_samples = Some(value(&arg, &mut args)?),
+                "--previews" => parsed.previews = true,
+                "--adaptive" => parsed.adaptive = Some(value(&arg, &mut args)?),
+                "--min-spp" => parsed.min_samples = Some(value(&arg, &mut args)?),
+                "--max-spp" => parsed.max_samples = Some(value(&arg, &mut args)?),
+                "--heatmap" => parsed.heatmap = Some(value(&arg, &mut args)?),
+                "--denoise" => parsed.denoise = true,
+                "--aov" => parsed.aovs = aov::parse_list(&value::<String>(&arg, &mut args)?)?,
+                "--checkpoint" => parsed.checkpoint = Some(value(&arg, &mut args)?),
+                "--checkpoint-every" => parsed.checkpoint_every = value(&arg, &mut args)?,
+                "--resume" => parsed.resume = Some(value(&arg, &mut args)?),
+                "--frames" => {
+                    let range: String = value(&arg, &mut args)?;
+                    parsed.frames = Some(range.parse()?);
+                }
+                "--shutter" => parsed.shutter_angle = Some(value(&arg, &mut args)?),
+                "-d" | "--depth" => parsed.max_depth = Some(value(&arg, &mut args)?),
+                "-i" | "--integrator" => parsed.integrator = value(&arg, &mut args)?,
+                "--sampler" => parsed.sampler = value(&arg, &mut args)?,
+                "--filter" => parsed.filter = value(&arg, &mut args)?,
+                "--bvh" => parsed.bvh = value(&arg, &mut args)?,
+                "--bvh-stats" => parsed.bvh_stats = true,
+                "-j" | "--threads" => parsed.threads = Some(value(&arg, &mut args)?),
+                "--tile-size" => parsed.tile_size = value(&arg, &mut args)?,
+                "--tile-order" => parsed.tile_order = value(&arg, &mut args)?,
+                "-o" | "--output" => parsed.output = value(&arg, &mut args)?,
+                "-f" | "--format" => {
+                    let name: String = value(&arg, &mut args)?;
+                    parsed.format = Some(
+                        OutputFormat::from_name(&name)
+                            .ok_or_else(|| format!("unknown output format \"{}\"", name))?,
+                    );
+                }
+                "-q" | "--quality" => parsed.quality = value(&arg, &mut args)?,
+                "-e" | "--exposure" => parsed.tone_mapper.exposure = value(&arg, &mut args)?,
+                "--tonemap" => parsed.tone_mapper.operator = value::<ToneMap>(&arg, &mut args)?,
+                "--transfer" => parsed.tone_mapper.transfer = value::<Transfer>(&arg, &mut args)?,
+                "--seed" => parsed.seed = Some(value(&arg, &mut args)?),
+                "--help" => parsed.help = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+                _ if parsed.scene.is_none() => parsed.scene = Some(arg),
+                _ => return Err(format!("unexpected argument \"{}\"", arg)),
+            }
+        }
+        if parsed.threads == Some(0) {
+            return Err(String::from("`--threads` must be at least 1"));
+        }
+        if parsed.pass_samples == Some(0) {
+            return Err(String::from("`--pass-spp` must be at least 1"));
+        }
+        if parsed
+            .adaptive
+            .is_some_and(|threshold| !threshold.is_finite() || threshold <= 0.0)
+        {
+            return Err(String::from("`--adaptive` must be positive"));
+        }
+        if parsed.tile_size == 0 {
+            return Err(String::from("`--tile-size` must be at least 1"));
+        }
+        if parsed.width == Some(0) || parsed.height == Some(0) {
+            return Err(String::from("image size must be at least 1x1"));
+        }
+        if parsed
+            .shutter_angle
+            .is_some_and(|angle| !(0.0..=360.0).contains(&angle))
+        {
+            return Err(String::from("`--shutter` must be from 0 to 360 degrees"));
+        }
+        // A resumed render keeps saving to the checkpoint it came from
+        if parsed.checkpoint.is_none() {
+            parsed.checkpoint = parsed.resume.clone();
+        }
+        Ok(parsed)
+    }
+
+    /// The output format, given explicitly or guessed from the output path.
+    pub fn output_format(&self) -> Result<OutputFormat, String> {
+        self.format
+            .or_else(|| OutputFormat::from_path(&self.output))
+            .ok_or_else(|| {
+                format!(
+                    "cannot tell the image format of \"{}\", use `--format`",
+                    self.output
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let args = parse(&[
+            "cornell_box",
+            "-W",
+            "300",
+            "--spp",
+            "16",
+            "-j",
+            "4",
+            "-o",
+            "out.png",
+            "--seed",
+            "7",
+        ])
+        .unwrap();
+        assert_eq!(args.scene.as_deref(), Some("cornell_box"));
+        assert_eq!(args.width, Some(300));
+        assert_eq!(args.height, None);
+        assert_eq!(args.samples_per_pixel, Some(16));
+        assert_eq!(args.threads, Some(4));
+        assert_eq!(args.seed, Some(7));
+        assert_eq!(args.output_format(), Ok(OutputFormat::Png));
+
+        let args = parse(&["--resume", "render.ckpt", "--sampler", "sobol"]).unwrap();
+        assert_eq!(args.sampler, SamplerKind::Sobol);
+        assert_eq!(args.checkpoint.as_deref(), Some("render.ckpt"));
+        let args = parse(&["--filter", "gaussian:2"]).unwrap();
+        assert_eq!(args.filter, Filter::Gaussian { radius: 2.0 });
+        let args = parse(&["--aov", "depth,samples"]).unwrap();
+        assert_eq!(args.aovs, vec![Aov::Depth, Aov::SampleCount]);
+        let args = parse(&["--frames", "1-24", "--shutter", "90"]).unwrap();
+        assert_eq!(args.frames, Some(FrameRange { first: 1, last: 24 }));
+        assert_eq!(args.shutter_angle, Some(90.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&["--spp"]).is_err());
+        assert!(parse(&["--spp", "many"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["-j", "0"]).is_err());
+        assert!(parse(&["--adaptive", "0"]).is_err());
+        assert!(parse(&["--adaptive", "NaN"]).is_err());
+        assert!(parse(&["-o", "out.bmp"]).unwrap().output_format().is_err());
+        assert!(parse(&["-f", "tiff"]).is_err());
+        assert!(parse(&["--aov", "depth,speed"]).is_err());
+        assert!(parse(&["--frames", "9-1"]).is_err());
+        assert!(parse(&["--shutter", "400"]).is_err());
+    }
+}
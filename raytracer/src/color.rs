@@ -1,7 +1,16 @@
-use image::RgbImage;
-/// the multi-sample write_color() function
-pub fn write_color(pixel_color: [u8; 3], img: &mut RgbImage, i: usize, j: usize) {
-    let pixel = img.get_pixel_mut(i.try_into().unwrap(), j.try_into().unwrap());
-    *pixel = image::Rgb(pixel_color);
-    // Write the translated [0,255] value of each color component.
+use crate::clamp;
+use crate::vec3::Color;
+
+/// Translates a display color into [0,255] values.
+pub fn quantize(display_color: Color) -> [u8; 3] {
+    [
+        (clamp(display_color.x(), 0.0, 0.999) * 255.).floor() as u8,
+        (clamp(display_color.y(), 0.0, 0.999) * 255.).floor() as u8,
+        (clamp(display_color.z(), 0.0, 0.999) * 255.).floor() as u8,
+    ]
+}
+
+/// Relative luminance of linear Rec. 709 primaries.
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
 }
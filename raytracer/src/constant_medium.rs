@@ -4,11 +4,13 @@ use std::{f64, sync::Arc};
 
 use rand::Rng;
 
+use crate::random;
 use crate::{
     hittable::{HitRecord, Hittable},
-    material::Material,
+    material::{Material, ScatterRecord},
+    pdf::SpherePdf,
     texture::{SolidColor, Texture},
-    vec3::Vec3,
+    vec3::{Color, Vec3},
     Ray,
 };
 
@@ -41,8 +43,13 @@ impl Hittable for ConstantMediun {
         self.boundary.bounding_box(time0, time1)
     }
 
-    fn hit(&self, r: &crate::Ray, t_min: f64, t_max: f64) -> Option<crate::hittable::HitRecord> {
-        let rbg = rand::thread_rng().gen_range(0.0..1.0);
+    fn hit(
+        &self,
+        r: &crate::Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<crate::hittable::HitRecord<'_>> {
+        let rbg = random::rng().gen_range(0.0..1.0);
         if let Some(mut hit_rec1) = self.boundary.hit(r, -f64::INFINITY, f64::INFINITY) {
             if let Some(mut hit_rec2) = self.boundary.hit(r, hit_rec1.t + 0.0001, f64::INFINITY) {
                 if hit_rec1.t < t_min {
@@ -98,6 +105,7 @@ impl Hittable for ConstantMediun {
                     u: 0.0,
                     v: 0.0,
                     front_face: true,
+                    object_id: 0,
                 })
             } else {
                 None
@@ -125,14 +133,19 @@ impl Iostropic {
 }
 
 impl Material for Iostropic {
-    fn scatter(
-        &self,
-        r_in: &crate::Ray,
-        rec: &crate::hittable::HitRecord,
-    ) -> Option<(crate::Ray, crate::vec3::Color)> {
-        Some((
-            Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.tm),
-            self.albedo.value(rec.u, rec.v, &rec.p),
-        ))
+    fn scatter(&self, _r_in: &crate::Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf_ptr: Some(Box::new(SpherePdf)),
+        })
+    }
+
+    fn scattering_pdf(&self, _r_in: &crate::Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * std::f64::consts::PI)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 }
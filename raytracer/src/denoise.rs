@@ -0,0 +1,308 @@
+use std::thread;
+
+use crate::color::luminance;
+use crate::framebuffer::Framebuffer;
+use crate::tile::default_threads;
+use crate::vec3::{Color, Vec3};
+
+/// Weights of the B3 spline taps at offsets -2..=2.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Variance of pixels too lonely to estimate one for, large enough that
+/// their luminance stops no neighbour from being averaged in.
+const UNKNOWN_VARIANCE: f64 = 1e6;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010), with the
+/// luminance weights of SVGF (Schied et al. 2017). Every iteration blurs
+/// with a 5x5 kernel whose taps spread twice as far as before, but only
+/// across pixels whose first hit normal and albedo match and whose colors
+/// differ by no more than their noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+    pub iterations: usize,
+    /// How many standard deviations of its noise a pixel's luminance may
+    /// differ from a neighbour's and still be averaged with it.
+    pub sigma_luminance: f64,
+    /// Exponent of the cosine between normals; higher keeps sharper edges.
+    pub sigma_normal: f64,
+    /// Albedo difference at which neighbours fall to `1/e` weight.
+    pub sigma_albedo: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+/// The per pixel values an iteration reads.
+struct Guide {
+    width: usize,
+    height: usize,
+    valid: Vec<bool>,
+    albedo: Vec<Color>,
+    normals: Vec<Vec3>,
+}
+
+impl Denoiser {
+    /// Returns a copy of `fb` with its radiance denoised. The framebuffer
+    /// must have been rendered with `Renderer::features` on.
+    pub fn denoise(&self, fb: &Framebuffer) -> Framebuffer {
+        let pixels: usize = fb.width * fb.height;
+        let mut color: Vec<Color> = (0..pixels)
+            .map(|i| fb.pixel(i % fb.width, i / fb.width))
+            .collect();
+        let guide = Guide {
+            width: fb.width,
+            height: fb.height,
+            valid: fb.weights.iter().map(|&w| w > 0.0).collect(),
+            albedo: (0..pixels)
+                .map(|i| fb.albedo[i] / (fb.samples[i].max(1) as f64))
+                .collect(),
+            normals: fb
+                .normals
+                .iter()
+                .map(|&n| {
+                    if n.length() > 1e-6 {
+                        Vec3::unit_vector(n)
+                    } else {
+                        Vec3::new(0.0, 0.0, 0.0)
+                    }
+                })
+                .collect(),
+        };
+        // Pixels with a single sample tell nothing of their noise, which
+        // their neighbourhood has to stand in for
+        let mut variance: Vec<f64> = (0..pixels)
+            .map(|i| {
+                let v: f64 = fb.mean_variance(i);
+                if v.is_finite() {
+                    v
+                } else {
+                    spatial_variance(&guide, &color, i)
+                }
+            })
+            .collect();
+
+        for iteration in 0..self.iterations {
+            (color, variance) = self.iterate(&guide, &color, &variance, 1 << iteration);
+        }
+
+        let mut denoised: Framebuffer = fb.clone();
+        for (i, c) in color.into_iter().enumerate() {
+            if guide.valid[i] {
+                denoised.sums[i] = c;
+                denoised.weights[i] = 1.0;
+            }
+        }
+        denoised
+    }
+
+    /// One à-trous pass with taps `step` pixels apart, split by rows over
+    /// all cores.
+    fn iterate(
+        &self,
+        guide: &Guide,
+        color: &[Color],
+        variance: &[f64],
+        step: usize,
+    ) -> (Vec<Color>, Vec<f64>) {
+        let mut next_color: Vec<Color> = vec![Color::new(0.0, 0.0, 0.0); color.len()];
+        let mut next_variance: Vec<f64> = vec![0.0; variance.len()];
+        let rows_per_thread: usize = guide.height.div_ceil(default_threads()).max(1);
+        let chunk: usize = rows_per_thread * guide.width;
+        thread::scope(|scope| {
+            for (n, (colors, variances)) in next_color
+                .chunks_mut(chunk)
+                .zip(next_variance.chunks_mut(chunk))
+                .enumerate()
+            {
+                scope.spawn(move || {
+                    for (k, (c, v)) in colors.iter_mut().zip(variances.iter_mut()).enumerate() {
+                        (*c, *v) = self.filter_pixel(guide, color, variance, step, n * chunk + k);
+                    }
+                });
+            }
+        });
+        (next_color, next_variance)
+    }
+
+    fn filter_pixel(
+        &self,
+        guide: &Guide,
+        color: &[Color],
+        variance: &[f64],
+        step: usize,
+        p: usize,
+    ) -> (Color, f64) {
+        if !guide.valid[p] {
+            return (color[p], variance[p]);
+        }
+        let (px, py) = ((p % guide.width) as i64, (p / guide.width) as i64);
+        let l_p: f64 = luminance(color[p]);
+        let noise: f64 = self.sigma_luminance * variance[p].sqrt() + 1e-6;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut sum_variance: f64 = 0.0;
+        let mut sum_weights: f64 = 0.0;
+        for (ky, hy) in KERNEL.iter().enumerate() {
+            let qy: i64 = py + (ky as i64 - 2) * step as i64;
+            if qy < 0 || qy >= guide.height as i64 {
+                continue;
+            }
+            for (kx, hx) in KERNEL.iter().enumerate() {
+                let qx: i64 = px + (kx as i64 - 2) * step as i64;
+                if qx < 0 || qx >= guide.width as i64 {
+                    continue;
+                }
+                let q: usize = qy as usize * guide.width + qx as usize;
+                if !guide.valid[q] {
+                    continue;
+                }
+                let w_luminance: f64 = (-(l_p - luminance(color[q])).abs() / noise).exp();
+                let w_albedo: f64 = (-(guide.albedo[p] - guide.albedo[q]).squared_length()
+                    / (self.sigma_albedo * self.sigma_albedo))
+                    .exp();
+                let w: f64 = hx * hy * w_luminance * w_albedo * self.normal_weight(guide, p, q);
+                if w == 0.0 {
+                    continue;
+                }
+                sum += w * color[q];
+                sum_variance += w * w * variance[q];
+                sum_weights += w;
+            }
+        }
+        // The pixel itself always has a positive weight
+        (
+            sum / sum_weights,
+            sum_variance / (sum_weights * sum_weights),
+        )
+    }
+
+    /// Neighbours facing away count less. Background pixels have no normal
+    /// and are only averaged with each other.
+    fn normal_weight(&self, guide: &Guide, p: usize, q: usize) -> f64 {
+        let (n_p, n_q) = (guide.normals[p], guide.normals[q]);
+        match (n_p.length() > 0.0, n_q.length() > 0.0) {
+            (true, true) => Vec3::dot(n_p, n_q).max(0.0).powf(self.sigma_normal),
+            (false, false) => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The variance of the luminance over the valid pixels in the 3x3 block
+/// around `p`.
+fn spatial_variance(guide: &Guide, color: &[Color], p: usize) -> f64 {
+    let (px, py) = (p % guide.width, p / guide.width);
+    let mut n: f64 = 0.0;
+    let mut sum: f64 = 0.0;
+    let mut squares: f64 = 0.0;
+    for qy in py.saturating_sub(1)..(py + 2).min(guide.height) {
+        for qx in px.saturating_sub(1)..(px + 2).min(guide.width) {
+            let q: usize = qy * guide.width + qx;
+            if guide.valid[q] {
+                let l: f64 = luminance(color[q]);
+                n += 1.0;
+                sum += l;
+                squares += l * l;
+            }
+        }
+    }
+    if n < 2.0 {
+        return UNKNOWN_VARIANCE;
+    }
+    ((squares - sum * sum / n) / (n - 1.0)).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::FirstHit;
+    use crate::framebuffer::TileBuffer;
+    use crate::tile::Tile;
+
+    /// A 16x16 image with a deterministic noise pattern, gray 0.5 on its
+    /// left half facing +z and 0.1 on its right half facing +x, except
+    /// for `background` columns on the right where rays miss.
+    fn noisy(samples: usize, background: usize) -> Framebuffer {
+        let tile = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 16,
+            y1: 16,
+        };
+        let mut buffer = TileBuffer::new(tile);
+        for y in 0..16 {
+            for x in 0..16 {
+                let normal = if x < 8 {
+                    Vec3::new(0.0, 0.0, 1.0)
+                } else {
+                    Vec3::new(1.0, 0.0, 0.0)
+                };
+                let gray: f64 = if x < 8 { 0.5 } else { 0.1 };
+                let offset: f64 = ((x * 7 + y * 13) % 5) as f64 * 0.15 - 0.3;
+                for s in 0..samples {
+                    let jitter: f64 = if s % 2 == 0 { 0.4 } else { -0.4 };
+                    let v: f64 = gray * (1.0 + offset + jitter);
+                    buffer.add(x, y, Color::new(v, v, v), 1.0);
+                    let miss = FirstHit::miss(Color::new(0.1, 0.1, 0.1));
+                    let hit = if x >= 16 - background {
+                        miss
+                    } else {
+                        FirstHit {
+                            albedo: Color::new(0.5, 0.5, 0.5),
+                            normal,
+                            ..miss
+                        }
+                    };
+                    buffer.add_hit(x, y, &hit);
+                }
+            }
+        }
+        let mut fb = Framebuffer::new(16, 16);
+        fb.merge(&buffer);
+        fb
+    }
+
+    fn error(fb: &Framebuffer, xs: std::ops::Range<usize>, expected: f64) -> f64 {
+        let mut sum: f64 = 0.0;
+        for y in 0..16 {
+            for x in xs.clone() {
+                sum += (fb.pixel(x, y).x() - expected).powi(2);
+            }
+        }
+        sum.sqrt()
+    }
+
+    #[test]
+    fn test_denoise_smooths_within_edges() {
+        let fb = noisy(4, 0);
+        let denoised = Denoiser::default().denoise(&fb);
+        assert!(error(&denoised, 0..8, 0.5) < 0.5 * error(&fb, 0..8, 0.5));
+        // Nothing leaks across the edge of the normals
+        assert!(error(&denoised, 8..16, 0.1) < error(&fb, 8..16, 0.1));
+        for y in 0..16 {
+            assert!(denoised.pixel(8, y).x() < 0.2);
+        }
+        assert_eq!(denoised.samples, fb.samples);
+
+        // A single sample per pixel, next to the background
+        let fb = noisy(1, 4);
+        assert_eq!(fb.mean_variance(0), f64::INFINITY);
+        let denoised = Denoiser::default().denoise(&fb);
+        for y in 0..16 {
+            for x in 0..16 {
+                assert!(denoised.pixel(x, y).x().is_finite());
+            }
+        }
+        assert!(error(&denoised, 0..8, 0.5) < error(&fb, 0..8, 0.5));
+        for y in 0..16 {
+            assert!(denoised.pixel(8, y).x() < 0.2);
+        }
+    }
+}
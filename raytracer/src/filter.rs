@@ -0,0 +1,176 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// Reconstruction filter weighting how much a sample counts towards the
+/// pixels around it, by its offset from their centers in pixels. All are
+/// separable and vanish beyond `radius` in x and y.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Equal weight everywhere. With radius 0.5 every sample only counts
+    /// in its own pixel.
+    Box { radius: f64 },
+    /// Falls off linearly to 0 at the radius.
+    Tent { radius: f64 },
+    /// A Gaussian with a standard deviation of a third of the radius,
+    /// shifted down to reach 0 at the radius.
+    Gaussian { radius: f64 },
+    /// Mitchell and Netravali's cubic with B = C = 1/3. Its negative lobes
+    /// sharpen, but can ring around edges.
+    Mitchell { radius: f64 },
+    /// Sinc windowed by a wider sinc, as sharp as it gets and ringing the
+    /// most.
+    Lanczos { radius: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Accepts `box`, `tent`, `gaussian`, `mitchell` and `lanczos`, each
+    /// optionally followed by `:RADIUS`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, radius) = match s.split_once(':') {
+            Some((name, r)) => match r.parse::<f64>() {
+                Ok(radius) if radius > 0.0 && radius.is_finite() => (name, Some(radius)),
+                _ => return Err(format!("invalid filter radius \"{}\"", r)),
+            },
+            None => (s, None),
+        };
+        match name {
+            "box" => Ok(Filter::Box {
+                radius: radius.unwrap_or(0.5),
+            }),
+            "tent" | "triangle" => Ok(Filter::Tent {
+                radius: radius.unwrap_or(1.0),
+            }),
+            "gaussian" => Ok(Filter::Gaussian {
+                radius: radius.unwrap_or(1.5),
+            }),
+            "mitchell" => Ok(Filter::Mitchell {
+                radius: radius.unwrap_or(2.0),
+            }),
+            "lanczos" => Ok(Filter::Lanczos {
+                radius: radius.unwrap_or(3.0),
+            }),
+            _ => Err(format!("unknown filter \"{}\"", s)),
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+/// The Mitchell-Netravali cubic on [-2,2].
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+    let x: f64 = x.abs();
+    if x >= 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius }
+            | Filter::Mitchell { radius }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    /// How many pixels beyond its own a sample taken in a pixel can reach.
+    pub fn margin(&self) -> usize {
+        (self.radius() - 0.5).ceil().max(0.0) as usize
+    }
+
+    fn eval_1d(&self, x: f64) -> f64 {
+        let radius: f64 = self.radius();
+        if x.abs() >= radius {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => radius - x.abs(),
+            Filter::Gaussian { .. } => {
+                let sigma: f64 = radius / 3.0;
+                gaussian(x, sigma) - gaussian(radius, sigma)
+            }
+            Filter::Mitchell { .. } => mitchell(2.0 * x / radius),
+            Filter::Lanczos { .. } => sinc(x) * sinc(x / radius),
+        }
+    }
+
+    /// The weight of a sample `(dx, dy)` pixels away from a pixel center.
+    /// The box includes its lower edges and excludes the upper ones, so
+    /// samples on a boundary between pixels count once.
+    pub fn eval(&self, dx: f64, dy: f64) -> f64 {
+        if let Filter::Box { radius } = *self {
+            let inside = |d: f64| -radius <= d && d < radius;
+            return if inside(dx) && inside(dy) { 1.0 } else { 0.0 };
+        }
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("box".parse(), Ok(Filter::Box { radius: 0.5 }));
+        assert_eq!("tent:1.5".parse(), Ok(Filter::Tent { radius: 1.5 }));
+        assert_eq!("lanczos".parse(), Ok(Filter::Lanczos { radius: 3.0 }));
+        assert!("gaussian:0".parse::<Filter>().is_err());
+        assert!("sinc".parse::<Filter>().is_err());
+        assert_eq!(Filter::default().margin(), 0);
+        assert_eq!(Filter::Mitchell { radius: 2.0 }.margin(), 2);
+    }
+
+    #[test]
+    fn test_shapes() {
+        for filter in ["box:1", "tent", "gaussian", "mitchell", "lanczos"] {
+            let filter: Filter = filter.parse().unwrap();
+            let r: f64 = filter.radius();
+            assert!(filter.eval(0.0, 0.0) > 0.0, "{:?}", filter);
+            assert_eq!(filter.eval(r, 0.0), 0.0, "{:?}", filter);
+            assert_eq!(filter.eval(0.3, -0.2), filter.eval(-0.3, 0.2));
+        }
+        let tent = Filter::Tent { radius: 1.0 };
+        assert_eq!(tent.eval(0.5, 0.0), 0.5);
+        // The Mitchell filter interpolates with weights summing to 1
+        let sum: f64 = (-2..=2).map(|k| mitchell(k as f64 + 0.25)).sum();
+        assert!((sum - 1.0).abs() < 1e-12);
+        assert!(Filter::Lanczos { radius: 3.0 }.eval(1.5, 0.0) < 0.0);
+        let boxed = Filter::default();
+        assert_eq!(boxed.eval(-0.5, 0.0), 1.0);
+        assert_eq!(boxed.eval(0.5, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,370 @@
+use image::{ImageBuffer, RgbImage};
+
+use crate::aov::FirstHit;
+use crate::color::luminance;
+use crate::filter::Filter;
+use crate::render::Lighting;
+use crate::tile::Tile;
+use crate::tonemap::ToneMapper;
+use crate::vec3::{Color, Vec3};
+
+/// Radiance accumulated for one tile by a single render thread. The
+/// buffer covers the tile and a margin around it, because the filter lets
+/// samples count in pixels next to their own.
+pub struct TileBuffer {
+    pub tile: Tile,
+    /// The pixels covered, the tile and its margin within the image.
+    pub region: Tile,
+    pub sums: Vec<Color>,
+    pub weights: Vec<f64>,
+    pub samples: Vec<u32>,
+    pub luminance_sums: Vec<f64>,
+    pub luminance_squares: Vec<f64>,
+    /// The weighted sums split by the bounces light took to the camera.
+    pub emission: Vec<Color>,
+    pub direct: Vec<Color>,
+    pub indirect: Vec<Color>,
+    /// Sums of the albedo and normal at the first hit of every sample,
+    /// when the renderer records first hits.
+    pub albedo: Vec<Color>,
+    pub normals: Vec<Vec3>,
+    /// Sums of the distance and texture coordinates of first hits, over
+    /// the `hits` samples that hit something.
+    pub depths: Vec<f64>,
+    pub uvs: Vec<(f64, f64)>,
+    pub hits: Vec<u32>,
+    /// Object and material key of the earliest sample to hit something.
+    pub object_ids: Vec<u32>,
+    pub material_ids: Vec<u32>,
+}
+
+impl TileBuffer {
+    pub fn new(tile: Tile) -> Self {
+        Self::with_region(tile, tile)
+    }
+
+    /// A buffer reaching `margin` pixels past the tile, but not outside a
+    /// `width` by `height` image.
+    pub fn with_margin(tile: Tile, margin: usize, width: usize, height: usize) -> Self {
+        let region = Tile {
+            x0: tile.x0.saturating_sub(margin),
+            y0: tile.y0.saturating_sub(margin),
+            x1: (tile.x1 + margin).min(width),
+            y1: (tile.y1 + margin).min(height),
+        };
+        Self::with_region(tile, region)
+    }
+
+    fn with_region(tile: Tile, region: Tile) -> Self {
+        Self {
+            tile,
+            region,
+            sums: vec![Color::new(0.0, 0.0, 0.0); region.pixels()],
+            weights: vec![0.0; region.pixels()],
+            samples: vec![0; region.pixels()],
+            luminance_sums: vec![0.0; region.pixels()],
+            luminance_squares: vec![0.0; region.pixels()],
+            emission: vec![Color::new(0.0, 0.0, 0.0); region.pixels()],
+            direct: vec![Color::new(0.0, 0.0, 0.0); region.pixels()],
+            indirect: vec![Color::new(0.0, 0.0, 0.0); region.pixels()],
+            albedo: vec![Color::new(0.0, 0.0, 0.0); region.pixels()],
+            normals: vec![Vec3::new(0.0, 0.0, 0.0); region.pixels()],
+            depths: vec![0.0; region.pixels()],
+            uvs: vec![(0.0, 0.0); region.pixels()],
+            hits: vec![0; region.pixels()],
+            object_ids: vec![0; region.pixels()],
+            material_ids: vec![0; region.pixels()],
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.region.y0) * self.region.width() + (x - self.region.x0)
+    }
+
+    /// Adds a weighted sample to pixel `(x, y)` of the image, which must lie
+    /// inside the tile.
+    pub fn add(&mut self, x: usize, y: usize, color: Color, weight: f64) {
+        let i: usize = self.index(x, y);
+        self.sums[i] += weight * color;
+        self.weights[i] += weight;
+        self.count(x, y, color);
+    }
+
+    /// Counts a sample taken in pixel `(x, y)` for the noise estimate,
+    /// without adding it to the image.
+    pub fn count(&mut self, x: usize, y: usize, color: Color) {
+        let i: usize = self.index(x, y);
+        self.samples[i] += 1;
+        let l: f64 = luminance(color);
+        self.luminance_sums[i] += l;
+        self.luminance_squares[i] += l * l;
+    }
+
+    /// Adds what a sample taken in pixel `(x, y)` hit first.
+    pub fn add_hit(&mut self, x: usize, y: usize, hit: &FirstHit) {
+        let i: usize = self.index(x, y);
+        self.albedo[i] += hit.albedo;
+        self.normals[i] += hit.normal;
+        if hit.t.is_finite() {
+            if self.hits[i] == 0 {
+                self.object_ids[i] = hit.object_id;
+                self.material_ids[i] = hit.material_id;
+            }
+            self.hits[i] += 1;
+            self.depths[i] += hit.t;
+            self.uvs[i].0 += hit.u;
+            self.uvs[i].1 += hit.v;
+        }
+    }
+
+    /// Adds a sample taken at `(x, y)` in continuous image coordinates to
+    /// every pixel of the region whose center `filter` reaches.
+    pub fn splat(&mut self, x: f64, y: f64, lighting: &Lighting, filter: &Filter) {
+        let color: Color = lighting.total();
+        let r: f64 = filter.radius();
+        // Pixels whose centers px + 0.5 lie in (x - r, x + r], as the box
+        // filter counts
+        let first = |v: f64, lo: usize| ((v - r - 0.5).floor() + 1.0).max(lo as f64) as usize;
+        let last =
+            |v: f64, hi: usize| ((v + r - 0.5).floor() + 1.0).min(hi as f64).max(0.0) as usize;
+        for py in first(y, self.region.y0)..last(y, self.region.y1) {
+            for px in first(x, self.region.x0)..last(x, self.region.x1) {
+                let weight: f64 = filter.eval(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+                if weight != 0.0 {
+                    let i: usize = self.index(px, py);
+                    self.sums[i] += weight * color;
+                    self.weights[i] += weight;
+                    self.emission[i] += weight * lighting.emission;
+                    self.direct[i] += weight * lighting.direct;
+                    self.indirect[i] += weight * lighting.indirect;
+                }
+            }
+        }
+    }
+}
+
+/// A floating point image holding the weighted sum of all samples taken in
+/// each pixel, how many there were, and the sums of their luminances and
+/// squared luminances to estimate the noise. Rows are counted from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub sums: Vec<Color>,
+    pub weights: Vec<f64>,
+    pub samples: Vec<u32>,
+    pub luminance_sums: Vec<f64>,
+    pub luminance_squares: Vec<f64>,
+    /// The weighted sums split by the bounces light took to the camera.
+    pub emission: Vec<Color>,
+    pub direct: Vec<Color>,
+    pub indirect: Vec<Color>,
+    /// Sums of the albedo and normal at the first hit of every sample,
+    /// when the renderer records first hits.
+    pub albedo: Vec<Color>,
+    pub normals: Vec<Vec3>,
+    /// Sums of the distance and texture coordinates of first hits, over
+    /// the `hits` samples that hit something.
+    pub depths: Vec<f64>,
+    pub uvs: Vec<(f64, f64)>,
+    pub hits: Vec<u32>,
+    /// Object and material key of the earliest sample to hit something.
+    pub object_ids: Vec<u32>,
+    pub material_ids: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            weights: vec![0.0; width * height],
+            samples: vec![0; width * height],
+            luminance_sums: vec![0.0; width * height],
+            luminance_squares: vec![0.0; width * height],
+            emission: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            direct: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            indirect: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            albedo: vec![Color::new(0.0, 0.0, 0.0); width * height],
+            normals: vec![Vec3::new(0.0, 0.0, 0.0); width * height],
+            depths: vec![0.0; width * height],
+            uvs: vec![(0.0, 0.0); width * height],
+            hits: vec![0; width * height],
+            object_ids: vec![0; width * height],
+            material_ids: vec![0; width * height],
+        }
+    }
+
+    /// Adds the samples of a finished tile and its margin.
+    pub fn merge(&mut self, buffer: &TileBuffer) {
+        let region: Tile = buffer.region;
+        for row in 0..region.height() {
+            let src = row * region.width();
+            let dst = (region.y0 + row) * self.width + region.x0;
+            for k in 0..region.width() {
+                self.sums[dst + k] += buffer.sums[src + k];
+                self.weights[dst + k] += buffer.weights[src + k];
+                self.samples[dst + k] += buffer.samples[src + k];
+                self.luminance_sums[dst + k] += buffer.luminance_sums[src + k];
+                self.luminance_squares[dst + k] += buffer.luminance_squares[src + k];
+                if self.hits[dst + k] == 0 {
+                    self.object_ids[dst + k] = buffer.object_ids[src + k];
+                    self.material_ids[dst + k] = buffer.material_ids[src + k];
+                }
+                self.emission[dst + k] += buffer.emission[src + k];
+                self.direct[dst + k] += buffer.direct[src + k];
+                self.indirect[dst + k] += buffer.indirect[src + k];
+                self.albedo[dst + k] += buffer.albedo[src + k];
+                self.normals[dst + k] += buffer.normals[src + k];
+                self.depths[dst + k] += buffer.depths[src + k];
+                self.uvs[dst + k].0 += buffer.uvs[src + k].0;
+                self.uvs[dst + k].1 += buffer.uvs[src + k].1;
+                self.hits[dst + k] += buffer.hits[src + k];
+            }
+        }
+    }
+
+    /// Adds all samples of another framebuffer of the same size.
+    pub fn accumulate(&mut self, other: &Framebuffer) {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        for i in 0..self.sums.len() {
+            self.sums[i] += other.sums[i];
+            self.weights[i] += other.weights[i];
+            self.samples[i] += other.samples[i];
+            self.luminance_sums[i] += other.luminance_sums[i];
+            self.luminance_squares[i] += other.luminance_squares[i];
+            // The earlier samples are in self
+            if self.hits[i] == 0 {
+                self.object_ids[i] = other.object_ids[i];
+                self.material_ids[i] = other.material_ids[i];
+            }
+            self.emission[i] += other.emission[i];
+            self.direct[i] += other.direct[i];
+            self.indirect[i] += other.indirect[i];
+            self.albedo[i] += other.albedo[i];
+            self.normals[i] += other.normals[i];
+            self.depths[i] += other.depths[i];
+            self.uvs[i].0 += other.uvs[i].0;
+            self.uvs[i].1 += other.uvs[i].1;
+            self.hits[i] += other.hits[i];
+        }
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.samples.iter().map(|&n| n as u64).sum()
+    }
+
+    /// The variance of the mean luminance of pixel `i`, estimated from its
+    /// samples. Infinite with fewer than two.
+    pub fn mean_variance(&self, i: usize) -> f64 {
+        let n: f64 = self.samples[i] as f64;
+        if n < 2.0 {
+            return f64::INFINITY;
+        }
+        let mean: f64 = self.luminance_sums[i] / n;
+        let variance: f64 =
+            ((self.luminance_squares[i] - mean * self.luminance_sums[i]) / (n - 1.0)).max(0.0);
+        variance / n
+    }
+
+    /// The standard error of the mean luminance of pixel `i`, relative to
+    /// that mean. Dark pixels are compared against `min_mean` instead, so
+    /// a little noise in them does not count as huge.
+    pub fn relative_error(&self, i: usize, min_mean: f64) -> f64 {
+        let mean: f64 = self.luminance_sums[i] / (self.samples[i].max(1) as f64);
+        self.mean_variance(i).sqrt() / mean.max(min_mean)
+    }
+
+    /// The mean radiance of pixel `(x, y)`, black if it has no samples.
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let i: usize = y * self.width + x;
+        if self.weights[i] > 0.0 {
+            self.sums[i] / self.weights[i]
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Converts to an 8-bit image with gamma 2.
+    pub fn to_rgb8(&self) -> RgbImage {
+        self.tone_mapped(&ToneMapper::default())
+    }
+
+    /// Converts to an 8-bit image for display.
+    pub fn tone_mapped(&self, tone_mapper: &ToneMapper) -> RgbImage {
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            image::Rgb(tone_mapper.to_rgb8(self.pixel(x as usize, y as usize)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_tiles() {
+        let mut fb = Framebuffer::new(4, 3);
+        let tile = Tile {
+            x0: 2,
+            y0: 1,
+            x1: 4,
+            y1: 3,
+        };
+        let mut buffer = TileBuffer::new(tile);
+        buffer.add(3, 2, Color::new(1.0, 0.5, 0.0), 1.0);
+        buffer.add(3, 2, Color::new(0.0, 0.5, 1.0), 1.0);
+        fb.merge(&buffer);
+
+        assert_eq!(fb.pixel(3, 2), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(fb.pixel(2, 1), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(fb.weights.iter().sum::<f64>(), 2.0);
+        assert_eq!(fb.samples[2 * 4 + 3], 2);
+        // Luminances 0.5702 and 0.4298, so a standard error of 0.0702
+        let error = fb.relative_error(2 * 4 + 3, 0.01);
+        assert!((error - 0.0702 / 0.5).abs() < 1e-9);
+        assert_eq!(fb.relative_error(0, 0.01), f64::INFINITY);
+        assert_eq!(fb.to_rgb8().get_pixel(3, 2).0, [180, 180, 180]);
+    }
+
+    #[test]
+    fn test_splat_across_tiles() {
+        let (width, height) = (4, 3);
+        let left = Tile {
+            x0: 0,
+            y0: 0,
+            x1: 2,
+            y1: 3,
+        };
+        let tent = Filter::Tent { radius: 1.0 };
+        let mut buffer = TileBuffer::with_margin(left, tent.margin(), width, height);
+        assert_eq!(buffer.region.x1, 3);
+        // A quarter pixel right of the center of (1, 1)
+        buffer.count(1, 1, Color::new(1.0, 1.0, 1.0));
+        buffer.splat(
+            1.75,
+            1.5,
+            &Lighting::emitted(Color::new(1.0, 1.0, 1.0)),
+            &tent,
+        );
+        let mut fb = Framebuffer::new(width, height);
+        fb.merge(&buffer);
+
+        assert_eq!(fb.weights[width + 1], 0.75);
+        assert_eq!(fb.weights[width + 2], 0.25);
+        assert_eq!(fb.weights[width], 0.0);
+        assert_eq!(fb.samples[width + 1], 1);
+        assert_eq!(fb.samples[width + 2], 0);
+
+        // The box filter only ever reaches the pixel the sample is in
+        let mut buffer = TileBuffer::new(left);
+        buffer.splat(
+            1.0,
+            2.0,
+            &Lighting::emitted(Color::new(1.0, 1.0, 1.0)),
+            &Filter::default(),
+        );
+        assert_eq!(buffer.weights, vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+}
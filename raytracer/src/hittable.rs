@@ -3,6 +3,7 @@ use std::sync::Arc;
 use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::transform::{AnimatedTransform, Transform};
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 
@@ -15,6 +16,8 @@ pub struct HitRecord<'a> {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    /// Number of the scene object hit, 0 unless it is `Tagged`.
+    pub object_id: u32,
 }
 
 impl<'a> HitRecord<'a> {
@@ -42,6 +45,7 @@ impl<'a> HitRecord<'a> {
             u,
             v,
             front_face,
+            object_id: 0,
         }
     }
 
@@ -55,163 +59,361 @@ impl<'a> HitRecord<'a> {
     // }
 }
 pub trait Hittable: Send + Sync {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Density of sampling direction `v` from `o` at `time` with
+    /// [`Hittable::random`].
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    /// A random direction from `o` towards this object as it is at `time`,
+    /// used to sample lights.
+    fn random(&self, _o: &Point3, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 
-pub struct Translate {
+/// Marks every hit of an object with its number, for the object ID pass.
+pub struct Tagged {
     pub ptr: Arc<dyn Hittable + Send + Sync>,
-    pub offset: Vec3,
+    pub id: u32,
 }
 
-impl Translate {
-    pub fn new(p: Arc<dyn Hittable + Send + Sync>, displacement: Vec3) -> Self {
-        Self {
-            ptr: p,
-            offset: displacement,
-        }
+impl Tagged {
+    pub fn new(p: Arc<dyn Hittable + Send + Sync>, id: u32) -> Self {
+        Self { ptr: p, id }
     }
 }
 
-impl Hittable for Translate {
+impl Hittable for Tagged {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec_hit = self.ptr.hit(r, t_min, t_max)?;
+        rec_hit.object_id = self.id;
+        Some(rec_hit)
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
-        if let Some(mut output_box) = self.ptr.bounding_box(time0, time1) {
-            output_box = Aabb::new(
-                output_box.min() + self.offset,
-                output_box.max() + self.offset,
-            );
-            Some(output_box)
-        } else {
-            None
-        }
+        self.ptr.bounding_box(time0, time1)
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let moved_r: Ray = Ray::new(r.orig - self.offset, r.dir, r.tm);
-        if let Some(rec_hit) = self.ptr.hit(&moved_r, t_min, t_max) {
-            let p = rec_hit.p + self.offset;
-            Some(HitRecord::new(
-                p,
-                rec_hit.t,
-                rec_hit.u,
-                rec_hit.v,
-                rec_hit.mat_ptr,
-                rec_hit.normal,
-                moved_r,
-            ))
-        } else {
-            None
-        }
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.ptr.pdf_value(o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f64) -> Vec3 {
+        self.ptr.random(o, time)
     }
 }
 
-pub struct RotateY {
+/// An object moved, rotated, scaled or sheared by an affine transform.
+/// Rays are taken into the object's space instead, so the object itself
+/// does not need to know.
+pub struct Transformed {
     pub ptr: Arc<dyn Hittable + Send + Sync>,
-    pub sin_theta: f64,
-    pub cos_theta: f64,
-    pub hasbox: bool,
-    pub box_: Aabb,
+    pub transform: Transform,
 }
 
+impl Transformed {
+    pub fn new(p: Arc<dyn Hittable + Send + Sync>, transform: Transform) -> Self {
+        Self { ptr: p, transform }
+    }
+}
+
+/// Hits `object` as placed in the world by `transform`.
+fn hit_transformed<'a>(
+    object: &'a (dyn Hittable + Send + Sync),
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    let object_r: Ray = transform.inverse_ray(r);
+    let mut rec_hit = object.hit(&object_r, t_min, t_max)?;
+    rec_hit.p = transform.point(rec_hit.p);
+    // Volumes have no normal to transform
+    if rec_hit.normal.length() > 0.0 {
+        rec_hit.normal = Vec3::unit_vector(transform.normal(rec_hit.normal));
+    }
+    Some(rec_hit)
+}
+
+/// The density of `object.random` as placed by `transform`, over world
+/// directions from `o`.
+fn pdf_transformed(
+    object: &(dyn Hittable + Send + Sync),
+    transform: &Transform,
+    o: &Point3,
+    v: &Vec3,
+    time: f64,
+) -> f64 {
+    let inverse: Transform = transform.inverse();
+    object.pdf_value(&inverse.point(*o), &inverse.vector(*v), time)
+        * transform.solid_angle_ratio(*v)
+}
+
+fn random_transformed(
+    object: &(dyn Hittable + Send + Sync),
+    transform: &Transform,
+    o: &Point3,
+    time: f64,
+) -> Vec3 {
+    let object_o: Point3 = transform.inverse().point(*o);
+    transform.vector(object.random(&object_o, time))
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_transformed(&*self.ptr, &self.transform, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let box_: Aabb = self.ptr.bounding_box(time0, time1)?;
+        Some(self.transform.aabb(&box_))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        pdf_transformed(&*self.ptr, &self.transform, o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f64) -> Vec3 {
+        random_transformed(&*self.ptr, &self.transform, o, time)
+    }
+}
+
+/// Moves an object by an offset. Kept for code written before
+/// `Transformed`, which it now builds.
+pub struct Translate;
+
+impl Translate {
+    #[deprecated(note = "use `Transformed::new(p, Transform::translate(displacement))`")]
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(p: Arc<dyn Hittable + Send + Sync>, displacement: Vec3) -> Transformed {
+        Transformed::new(p, Transform::translate(displacement))
+    }
+}
+
+/// Turns an object about the y axis by an angle in degrees. Kept for code
+/// written before `Transformed`, which it now builds.
+pub struct RotateY;
+
 impl RotateY {
-    pub fn new(p: Arc<dyn Hittable + Send + Sync>, angle: f64) -> Self {
-        let radians = angle * std::f64::consts::PI / 180.0;
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        if let Some(bbox) = p.bounding_box(0.0, 1.0) {
-            let mut min = Point3::new(std::f64::INFINITY, std::f64::INFINITY, std::f64::INFINITY);
-            let mut max = Point3::new(
-                -std::f64::INFINITY,
-                -std::f64::INFINITY,
-                -std::f64::INFINITY,
-            );
-            for i in 0..2 {
-                for j in 0..2 {
-                    for k in 0..2 {
-                        let x = i as f64 * bbox.max().x() + (1.0 - i as f64) * bbox.min().x();
-                        let y = j as f64 * bbox.max().y() + (1.0 - j as f64) * bbox.min().y();
-                        let z = k as f64 * bbox.max().z() + (1.0 - k as f64) * bbox.min().z();
-
-                        let newx = cos_theta * x + sin_theta * z;
-                        let newz = -sin_theta * x + cos_theta * z;
-
-                        let tester = Vec3::new(newx, y, newz);
-                        let min_x = min.x().min(tester.x());
-                        let max_x = max.x().max(tester.x());
-                        let min_y = min.y().min(tester.y());
-                        let max_y = max.y().max(tester.y());
-                        let min_z = min.z().min(tester.z());
-                        let max_z = max.z().max(tester.z());
-                        min = Vec3::new(min_x, min_y, min_z);
-                        max = Vec3::new(max_x, max_y, max_z);
-                    }
-                }
-            }
-            let box_ = Aabb::new(min, max);
-            Self {
-                ptr: p,
-                sin_theta,
-                cos_theta,
-                hasbox: true,
-                box_,
-            }
-        } else {
-            Self {
-                ptr: p,
-                sin_theta,
-                cos_theta,
-                hasbox: false,
-                box_: Aabb::new(Vec3::zero(), Vec3::zero()),
-            }
+    #[deprecated(note = "use `Transformed::new(p, Transform::rotate_y(angle))`")]
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(p: Arc<dyn Hittable + Send + Sync>, angle: f64) -> Transformed {
+        Transformed::new(p, Transform::rotate_y(angle))
+    }
+}
+
+/// An object moving, turning and growing over the shutter interval, placed
+/// by the transform of `motion` at the time of each ray.
+pub struct Animated {
+    pub ptr: Arc<dyn Hittable + Send + Sync>,
+    pub motion: AnimatedTransform,
+}
+
+impl Animated {
+    pub fn new(p: Arc<dyn Hittable + Send + Sync>, motion: AnimatedTransform) -> Self {
+        Self { ptr: p, motion }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_transformed(&*self.ptr, &self.motion.at(r.tm), r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let box_: Aabb = self.ptr.bounding_box(time0, time1)?;
+        Some(self.motion.aabb(&box_, time0, time1))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        pdf_transformed(&*self.ptr, &self.motion.at(time), o, v, time)
+    }
+
+    fn random(&self, o: &Point3, time: f64) -> Vec3 {
+        random_transformed(&*self.ptr, &self.motion.at(time), o, time)
+    }
+}
+
+/// One copy of a shared prototype object, often a whole BVH, placed by its
+/// own transform and optionally painted with another material. Thousands
+/// of instances cost a transform each instead of a copy of the geometry,
+/// and a BVH over them makes a two-level hierarchy.
+pub struct Instance {
+    pub object: Transformed,
+    /// Replaces the materials of the prototype when set.
+    pub material: Option<Arc<dyn Material + Send + Sync>>,
+}
+
+impl Instance {
+    pub fn new(prototype: Arc<dyn Hittable + Send + Sync>, transform: Transform) -> Self {
+        Self {
+            object: Transformed::new(prototype, transform),
+            material: None,
+        }
+    }
+
+    pub fn with_material(
+        prototype: Arc<dyn Hittable + Send + Sync>,
+        transform: Transform,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        Self {
+            object: Transformed::new(prototype, transform),
+            material: Some(material),
         }
     }
 }
 
-impl Hittable for RotateY {
-    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
-        Some(self.box_.clone())
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec_hit = self.object.hit(r, t_min, t_max)?;
+        if let Some(material) = &self.material {
+            rec_hit.mat_ptr = &**material;
+        }
+        Some(rec_hit)
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.orig;
-        let mut direction = r.dir;
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
 
-        let origin_x = self.cos_theta * r.orig.x() - self.sin_theta * r.orig.z();
-        let origin_z = self.sin_theta * r.orig.x() + self.cos_theta * r.orig.z();
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        self.object.pdf_value(o, v, time)
+    }
 
-        let direction_x = self.cos_theta * r.dir.x() - self.sin_theta * r.dir.z();
-        let direction_z = self.sin_theta * r.dir.x() + self.cos_theta * r.dir.z();
+    fn random(&self, o: &Point3, time: f64) -> Vec3 {
+        self.object.random(o, time)
+    }
+}
 
-        origin = Vec3::new(origin_x, origin.y(), origin_z);
-        direction = Vec3::new(direction_x, direction.y(), direction_z);
-        let rotated_r = Ray::new(origin, direction, r.tm);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::transform::Mat4;
+    use crate::vec3::Color;
 
-        if let Some(rec_hit) = self.ptr.hit(&rotated_r, t_min, t_max) {
-            let mut p = rec_hit.p;
-            let mut normal = rec_hit.normal;
+    #[test]
+    fn test_transformed_sphere() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+        // An ellipsoid 4 wide, 2 high and deep, centered at (3, 0, 0)
+        let ellipsoid = Transformed::new(
+            Arc::new(sphere),
+            Transform::translate(Vec3::new(3.0, 0.0, 0.0))
+                * Transform::scale(Vec3::new(2.0, 1.0, 1.0)),
+        );
+        let box_ = ellipsoid.bounding_box(0.0, 1.0).unwrap();
+        assert_eq!(box_.min(), Point3::new(1.0, -1.0, -1.0));
+        assert_eq!(box_.max(), Point3::new(5.0, 1.0, 1.0));
 
-            let p_x = self.cos_theta * rec_hit.p.x() + self.sin_theta * rec_hit.p.z();
-            let p_z = -self.sin_theta * rec_hit.p.x() + self.cos_theta * rec_hit.p.z();
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.5, 0.0, 0.0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        assert!((rec.p - Point3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
 
-            let normal_x =
-                self.cos_theta * rec_hit.normal.x() + self.sin_theta * rec_hit.normal.z();
-            let normal_z =
-                -self.sin_theta * rec_hit.normal.x() + self.cos_theta * rec_hit.normal.z();
+        // Where the surface slopes, the normal is not the scaled one
+        let r = Ray::new(Point3::new(4.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let expected = Vec3::unit_vector(Vec3::new(0.25, 0.75_f64.sqrt(), 0.0));
+        assert!((rec.normal - expected).length() < 1e-9);
+        assert!(rec.front_face);
+    }
 
-            p = Vec3::new(p_x, p.y(), p_z);
-            normal = Vec3::new(normal_x, normal.y(), normal_z);
-            Some(HitRecord::new(
-                p,
-                rec_hit.t,
-                rec_hit.u,
-                rec_hit.v,
-                rec_hit.mat_ptr,
-                normal,
-                rotated_r,
-            ))
-        } else {
-            None
+    #[test]
+    fn test_transformed_light_pdf_integrates_to_one() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+        let sheared = Transform::new(Mat4::new([
+            [2.0, 0.5, 0.0, 3.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.5, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]))
+        .unwrap();
+        let light = Transformed::new(Arc::new(sphere), sheared);
+        let o = Point3::new(0.0, 0.0, 0.0);
+        // Midpoint rule over the directions of the sphere
+        let n: usize = 400;
+        let pi: f64 = std::f64::consts::PI;
+        let mut integral: f64 = 0.0;
+        for i in 0..n {
+            let theta: f64 = (i as f64 + 0.5) / n as f64 * pi;
+            for j in 0..2 * n {
+                let phi: f64 = (j as f64 + 0.5) / n as f64 * pi;
+                let v = Vec3::new(
+                    theta.cos(),
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                integral += light.pdf_value(&o, &v, 0.0) * theta.sin() * (pi / n as f64).powi(2);
+            }
         }
+        assert!((integral - 1.0).abs() < 1e-2, "{}", integral);
+        let v: Vec3 = light.random(&o, 0.0);
+        assert!(light
+            .hit(&Ray::new(o, v, 0.0), 0.001, f64::INFINITY)
+            .is_some());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_translate_and_rotate_y() {
+        let sphere: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(
+            Point3::new(1.0, 0.0, 0.0),
+            0.5,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        ));
+        let moved = Translate::new(sphere.clone(), Vec3::new(0.0, 2.0, 0.0));
+        let box_ = moved.bounding_box(0.0, 1.0).unwrap();
+        assert!((box_.min() - Point3::new(0.5, 1.5, -0.5)).length() < 1e-9);
+        // A quarter turn takes +x to -z
+        let turned = RotateY::new(sphere, 90.0);
+        let box_ = turned.bounding_box(0.0, 1.0).unwrap();
+        assert!((box_.min() - Point3::new(-0.5, -0.5, -1.5)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_instances_share_a_prototype() {
+        let prototype: Arc<dyn Hittable + Send + Sync> = Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        ));
+        let red: Arc<dyn Material + Send + Sync> =
+            Arc::new(Lambertian::new(Color::new(0.9, 0.1, 0.1)));
+        let plain = Instance::new(
+            prototype.clone(),
+            Transform::translate(Vec3::new(-3.0, 0.0, 0.0)),
+        );
+        let painted = Instance::with_material(
+            prototype.clone(),
+            Transform::translate(Vec3::new(3.0, 0.0, 0.0)),
+            red.clone(),
+        );
+
+        let address = |m: &dyn Material| m as *const dyn Material as *const ();
+        let down = |x: f64| Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let original = prototype.hit(&down(0.0), 0.001, f64::INFINITY).unwrap();
+        let rec = plain.hit(&down(-3.0), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(-3.0, 1.0, 0.0)).length() < 1e-9);
+        assert_eq!(address(rec.mat_ptr), address(original.mat_ptr));
+        let rec = painted.hit(&down(3.0), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(3.0, 1.0, 0.0)).length() < 1e-9);
+        assert_eq!(address(rec.mat_ptr), address(&*red));
+        assert!(plain.hit(&down(3.0), 0.001, f64::INFINITY).is_none());
     }
 }
@@ -4,11 +4,13 @@ use crate::aabb::Aabb;
 use crate::hittable::HitRecord;
 use crate::hittable::Hittable;
 // use crate::material::Material;
+use crate::random;
 use crate::ray::Ray;
 // use crate::sphere::MovingSphere;
-use crate::vec3::Vec3;
+use crate::vec3::{Point3, Vec3};
+use rand::Rng;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct HittableList {
     pub hittable_list: Vec<Arc<dyn Hittable + Send + Sync>>,
 }
@@ -27,10 +29,14 @@ impl HittableList {
     pub fn add(&mut self, object: Arc<dyn Hittable + Send + Sync>) {
         self.hittable_list.push(object);
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.hittable_list.is_empty()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut hit_anything: Option<HitRecord> = None;
         let mut closest_so_far: f64 = t_max;
 
@@ -66,4 +72,17 @@ impl Hittable for HittableList {
         }
         Some(output_box)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        let weight: f64 = 1.0 / self.hittable_list.len() as f64;
+        self.hittable_list
+            .iter()
+            .map(|object| weight * object.pdf_value(o, v, time))
+            .sum()
+    }
+
+    fn random(&self, o: &Point3, time: f64) -> Vec3 {
+        let index: usize = random::rng().gen_range(0..self.hittable_list.len());
+        self.hittable_list[index].random(o, time)
+    }
 }
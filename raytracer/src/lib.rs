@@ -0,0 +1,66 @@
+pub mod aabb;
+pub mod aarect;
+pub mod animation;
+pub mod aov;
+pub mod bbox;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod color;
+pub mod constant_medium;
+pub mod denoise;
+pub mod filter;
+pub mod framebuffer;
+pub mod hittable;
+pub mod hittable_list;
+pub mod material;
+pub mod mesh;
+pub mod obj;
+pub mod onb;
+pub mod output;
+pub mod pdf;
+pub mod perlin;
+pub mod random;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod scene;
+pub mod scenes;
+pub mod sphere;
+pub mod texture;
+pub mod tile;
+pub mod tonemap;
+pub mod transform;
+pub mod triangle;
+pub mod vec3;
+
+pub use animation::{Animation, FrameRange, Keyframes};
+pub use aov::Aov;
+pub use bvh::{BVHNode, BvhStats, LinearBVH, SplitMethod};
+pub use camera::Camera;
+pub use checkpoint::Checkpoint;
+pub use denoise::Denoiser;
+pub use filter::Filter;
+pub use framebuffer::Framebuffer;
+pub use hittable::{Animated, HitRecord, Hittable, Instance, Transformed};
+pub use hittable_list::HittableList;
+pub use material::Material;
+pub use mesh::TriangleMesh;
+pub use ray::Ray;
+pub use render::{AdaptiveSampling, Integrator, Lighting, Progress, Renderer};
+pub use scene::{Scene, SceneError};
+pub use texture::Texture;
+pub use tile::TileOrder;
+pub use tonemap::{ToneMap, ToneMapper, Transfer};
+pub use transform::{AnimatedTransform, Mat4, Pose, Quat, Transform};
+pub use vec3::{Color, Point3, Vec3};
+
+pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    if x < min {
+        return min;
+    }
+    if x > max {
+        return max;
+    }
+    x
+}
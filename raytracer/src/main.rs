@@ -1,40 +1,18 @@
-mod aabb;
-mod aarect;
-mod bbox;
-mod bvh;
-mod camera;
-mod color;
-mod constant_medium;
-mod hittable;
-mod hittable_list;
-mod material;
-mod perlin;
-mod ray;
-mod sphere;
-mod texture;
-mod vec3;
-
-use crate::material::{Dielectric, Lambertian, Metal};
-use crate::vec3::{Color, Point3, Vec3};
-use aarect::{XyRect, XzRect, YzRect};
-use bbox::Box_;
-use bvh::BVHNode;
-use camera::Camera;
-use color::write_color;
-use constant_medium::ConstantMediun;
-use hittable::{Hittable, RotateY, Translate};
-use hittable_list::HittableList;
-use image::ImageBuffer;
-use indicatif::ProgressBar;
-use material::DiffuseLight;
-use rand::Rng;
-pub use ray::Ray;
-use sphere::{MovingSphere, Sphere};
-use std::fs::File;
-use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Instant;
-use texture::{CheckerTexture, ImageTexture, NoiseTexture};
+mod cli;
+
+use cli::{Args, USAGE};
+use rand::SeedableRng;
+use raytracer::checkpoint::{self, Checkpoint};
+use raytracer::hittable::Tagged;
+use raytracer::random::{self, Pcg32};
+use raytracer::{
+    output, scenes, AdaptiveSampling, Animation, Aov, Denoiser, Framebuffer, Hittable, LinearBVH,
+    Progress, Renderer, Scene,
+};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const AUTHOR: &str = "程婧祎";
 
@@ -42,602 +20,336 @@ fn is_ci() -> bool {
     option_env!("CI").unwrap_or_default() == "true"
 }
 
-fn random_scene() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let material_ground: Lambertian = Lambertian::new(Color::new(0.5, 0.5, 0.5));
-    world.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        material_ground,
-    )));
+fn main() {
+    let now = Instant::now();
 
-    let checker = Arc::new(CheckerTexture::new_from_color(
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Lambertian::new_arc(checker),
-    )));
+    // get environment variable CI, which is true for GitHub Actions
+    let is_ci: bool = is_ci();
 
-    let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
-    for a in -11..11 {
-        for b in -11..11 {
-            let choosemat: f64 = rng.gen();
-            let deltax: f64 = rng.gen();
-            let deltaz: f64 = rng.gen();
-            let center: Point3 = Point3::new(a as f64 + 0.9 * deltax, 0.2, b as f64 + 0.9 * deltaz);
+    println!("CI: {}", is_ci);
 
-            if (center - Point3::new(4.0, 0.2, 0.0)).length() > 0.9 {
-                if choosemat < 0.8 {
-                    let r: f64 = rng.gen_range(0.0..1.0);
-                    let g: f64 = rng.gen_range(0.0..1.0);
-                    let b: f64 = rng.gen_range(0.0..1.0);
-                    let p: Vec3 = Vec3::new(r, g, b);
-                    let albedo: Vec3 = p * p;
-                    let center2: Vec3 = center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0);
-                    let sphere_material = Lambertian::new(albedo);
-                    world.add(Arc::new(MovingSphere::new(
-                        center,
-                        center2,
-                        0.0,
-                        1.0,
-                        0.2,
-                        sphere_material,
-                    )));
-                } else if choosemat < 0.95 {
-                    let r: f64 = rng.gen_range(0.5..1.0);
-                    let g: f64 = rng.gen_range(0.5..1.0);
-                    let b: f64 = rng.gen_range(0.5..1.0);
-                    let albedo: Vec3 = Vec3::new(r, g, b);
-                    let fuzz = rng.gen_range(0.0..0.5);
-                    let sphere_material = Metal::new(albedo, fuzz);
-                    HittableList::add(
-                        &mut world,
-                        Arc::new(Sphere::new(center, 0.2, sphere_material)),
-                    );
-                } else {
-                    let sphere_material = Dielectric::new(1.5);
-                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = args.output_format() {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    }
+    let path: &str = &args.output;
+
+    // A resumed render carries on with the seed it was started with
+    let resumed: Option<Checkpoint> =
+        args.resume
+            .as_deref()
+            .map(|p| match Checkpoint::load(Path::new(p)) {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => {
+                    eprintln!("Cannot resume from \"{}\": {}", p, e);
+                    std::process::exit(1);
                 }
+            });
+    let seed: u64 = args
+        .seed
+        .or(resumed.as_ref().map(|c| c.seed))
+        .unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+
+    // Scene names ending in .toml are read from file, anything else is built in
+    let scene_name = args.scene.as_deref().unwrap_or("final");
+    let scene: Scene = load_scene(scene_name, seed, None);
+    // Scene files with an `[animation]` table render their frames
+    let animation: Option<Animation> = match (args.frames, scene.animation) {
+        (None, None) => None,
+        (Some(_), _) if !scene_name.ends_with(".toml") => {
+            eprintln!("Built-in scenes are not animated, `--frames` needs a .toml file");
+            std::process::exit(2);
+        }
+        (frames, animation) => {
+            let mut animation: Animation = animation.unwrap_or_default();
+            if let Some(frames) = frames {
+                animation.first_frame = frames.first;
+                animation.last_frame = frames.last;
             }
+            animation.shutter_angle = args.shutter_angle.unwrap_or(animation.shutter_angle);
+            Some(animation)
         }
+    };
+    if animation.is_some() && args.checkpoint.is_some() {
+        eprintln!("`--checkpoint` and `--resume` work on single images, not animations");
+        std::process::exit(2);
     }
-    let material1 = Dielectric::new(1.5);
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 1.0, 0.0),
-        1.0,
-        material1,
-    )));
-
-    let material2 = Lambertian::new(Color::new(0.4, 0.2, 0.1));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(-4.0, 1.0, 0.0),
-        1.0,
-        material2,
-    )));
-
-    let material3 = Metal::new(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.add(Arc::new(Sphere::new(
-        Point3::new(4.0, 1.0, 0.0),
-        1.0,
-        material3,
-    )));
-
-    world
-}
-
-fn two_speres() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let checker = Arc::new(CheckerTexture::new_from_color(
-        Color::new(0.2, 0.3, 0.1),
-        Color::new(0.9, 0.9, 0.9),
-    ));
-    let checker1 = checker.clone();
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -10.0, 0.0),
-        10.0,
-        Lambertian::new_arc(checker),
-    )));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 10.0, 0.0),
-        10.0,
-        Lambertian::new_arc(checker1),
-    )));
-    world
-}
-
-fn two_perlin_spheres() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let pertext = Arc::new(NoiseTexture::new_sc(4.0));
-    let pertext1 = Arc::new(NoiseTexture::new_sc(4.0));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Lambertian::new_arc(pertext),
-    )));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Lambertian::new_arc(pertext1),
-    )));
-    world
-}
-
-fn earth_scene() -> HittableList {
-    let mut world: HittableList = HittableList::new();
-    let earth_texture: Arc<ImageTexture> = Arc::new(ImageTexture::new("earthmap.jpg"));
-    let earth_surface: Lambertian = Lambertian::new_arc(earth_texture);
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 0.0, 0.0),
-        2.0,
-        earth_surface,
-    )));
-    world
-}
-
-fn simple_light() -> HittableList {
-    let mut world = HittableList::new();
-    let pertext = Arc::new(NoiseTexture::new_sc(4.0));
-    let pertext1 = Arc::new(NoiseTexture::new_sc(4.0));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Lambertian::new_arc(pertext),
-    )));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 2.0, 0.0),
-        2.0,
-        Lambertian::new_arc(pertext1),
-    )));
-
-    let difflight = Arc::new(DiffuseLight::new_col(Color::new(4.0, 4.0, 4.0)));
-    let difflight1 = DiffuseLight::new_col(Color::new(4.0, 4.0, 4.0));
-    world.add(Arc::new(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight)));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 7.0, 0.0),
-        2.0,
-        difflight1,
-    )));
-    world
-}
-
-fn cornell_box() -> HittableList {
-    let mut world = HittableList::new();
-
-    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
-    let white1 = white.clone();
-    let white2 = white1.clone();
-    let white3 = white2.clone();
-    let white4 = white3.clone();
-    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
-    let light = Arc::new(DiffuseLight::new_col(Color::new(15.0, 15.0, 15.0)));
-
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    world.add(Arc::new(XzRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
-    world.add(Arc::new(XzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white)));
-    world.add(Arc::new(XzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white1)));
-    world.add(Arc::new(XyRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white2)));
-
-    world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(Box_::new(
-                Point3::new(0.0, 0.0, 0.0),
-                Point3::new(165.0, 330.0, 165.0),
-                white3,
-            )),
-            15.0,
-        )),
-        Vec3::new(265.0, 0.0, 295.0),
-    )));
-    world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(Box_::new(
-                Point3::new(0.0, 0.0, 0.0),
-                Point3::new(165.0, 165.0, 165.0),
-                white4,
-            )),
-            -18.0,
-        )),
-        Vec3::new(130.0, 0.0, 65.0),
-    )));
-
-    world
-}
-
-fn cornell_smoke() -> HittableList {
-    let mut world = HittableList::new();
-    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
-    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
-    let white1 = white.clone();
-    let white2 = white1.clone();
-    let white3 = white2.clone();
-    let white4 = white3.clone();
-    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
-    let light: Arc<DiffuseLight> = Arc::new(DiffuseLight::new_col(Color::new(7.0, 7.0, 7.0)));
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    world.add(Arc::new(XzRect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    )));
-    world.add(Arc::new(XzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white)));
-    world.add(Arc::new(XzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white1)));
-    world.add(Arc::new(XyRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white2)));
-
-    world.add(Arc::new(ConstantMediun::new_col(
-        Arc::new(Translate::new(
-            Arc::new(RotateY::new(
-                Arc::new(Box_::new(
-                    Point3::new(0.0, 0.0, 0.0),
-                    Point3::new(165.0, 330.0, 165.0),
-                    white3,
-                )),
-                15.0,
-            )),
-            Vec3::new(265.0, 0.0, 295.0),
-        )),
-        0.01,
-        Vec3::new(0.0, 0.0, 0.0),
-    )));
-    world.add(Arc::new(ConstantMediun::new_col(
-        Arc::new(Translate::new(
-            Arc::new(RotateY::new(
-                Arc::new(Box_::new(
-                    Point3::new(0.0, 0.0, 0.0),
-                    Point3::new(165.0, 165.0, 165.0),
-                    white4,
-                )),
-                -18.0,
-            )),
-            Vec3::new(130.0, 0.0, 65.0),
-        )),
-        0.01,
-        Vec3::new(1.0, 1.0, 1.0),
-    )));
-    world
-}
-
-fn final_scene() -> HittableList {
-    let mut boxes1 = HittableList::new();
-    let ground = Arc::new(Lambertian::new(Vec3::new(0.48, 0.83, 0.53)));
-    const BOXES_PER_SIDE: i32 = 20;
-    let mut rng = rand::thread_rng();
-    for i in 0..BOXES_PER_SIDE {
-        for j in 0..BOXES_PER_SIDE {
-            for _ in 0..BOXES_PER_SIDE {
-                let w = 100.0;
-                let x0 = -1000.0 + i as f64 * w;
-                let z0 = -1000.0 + j as f64 * w;
-                let y0 = 0.0;
-                let x1 = x0 + w;
-                let y1 = rng.gen_range(1.0..101.0);
-                let z1 = z0 + w;
-
-                boxes1.add(Arc::new(Box_::new(
-                    Point3::new(x0, y0, z0),
-                    Point3::new(x1, y1, z1),
-                    ground.clone(),
-                )));
+    // Ctrl-C abandons the current pass and saves what is finished
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    if args.checkpoint.is_some() {
+        let stop = stop.clone();
+        if let Err(e) = ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed)) {
+            eprintln!("Cannot catch Ctrl-C: {}", e);
+        }
+    }
+    match animation {
+        Some(animation) => {
+            for frame in animation.frames() {
+                let (time0, time1) = animation.shutter(frame);
+                println!("Frame {} at {:.3}s", frame, time0);
+                let scene: Scene = load_scene(scene_name, seed, Some((time0, time1)));
+                let path = output::frame_path(path, frame);
+                render(&args, scene, scene_name, seed, None, &path, &stop);
             }
         }
+        None => render(
+            &args,
+            scene,
+            scene_name,
+            seed,
+            resumed,
+            Path::new(path),
+            &stop,
+        ),
     }
+    let end = now.elapsed().as_secs();
+    println!("程序运行了 {} 秒", end);
+}
 
-    let mut world = HittableList::new();
-    world.add(BVHNode::new_boxed(boxes1, 0.0, 1.0));
-    let light: Arc<DiffuseLight> = Arc::new(DiffuseLight::new_col(Color::new(7.0, 7.0, 7.0)));
-    world.add(Arc::new(XzRect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    )));
-
-    let center1 = Point3::new(400.0, 400.0, 200.0);
-    let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
-    let moving_sphere_material = Lambertian::new(Vec3::new(0.7, 0.3, 0.1));
-
-    world.add(Arc::new(MovingSphere::new(
-        center1,
-        center2,
-        0.0,
-        1.0,
-        50.0,
-        moving_sphere_material,
-    )));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(260.0, 150.0, 45.0),
-        50.0,
-        Dielectric::new(1.5),
-    )));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(0.0, 150.0, 145.0),
-        50.0,
-        Metal::new(Color::new(0.8, 0.8, 0.9), 1.0),
-    )));
-
-    let boundary = Arc::new(Sphere::new(
-        Point3::new(360.0, 150.0, 145.0),
-        70.0,
-        Dielectric::new(1.5),
-    ));
-    world.add(boundary.clone());
-    world.add(Arc::new(ConstantMediun::new_col(
-        boundary,
-        0.2,
-        Color::new(0.2, 0.4, 0.9),
-    )));
-    let boundary1 = Arc::new(Sphere::new(
-        Point3::new(0.0, 0.0, 0.0),
-        5000.0,
-        Dielectric::new(1.5),
-    ));
-    world.add(Arc::new(ConstantMediun::new_col(
-        boundary1,
-        0.0001,
-        Color::new(1.0, 1.0, 1.0),
-    )));
-
-    let emat = Lambertian::new_arc(Arc::new(ImageTexture::new("earthmap.jpg")));
-    world.add(Arc::new(Sphere::new(
-        Point3::new(400.0, 200.0, 400.0),
-        100.0,
-        emat,
-    )));
-
-    world.add(Arc::new(Sphere::new(
-        Point3::new(220.0, 280.0, 300.0),
-        80.0,
-        Lambertian::new_arc(Arc::new(NoiseTexture::new_sc(0.1))),
-    )));
-
-    let mut boxes2 = HittableList::new();
-    let mut rng = rand::thread_rng();
-    for _ in 0..1000 {
-        let x = rng.gen_range(0.0..165.0);
-        let y = rng.gen_range(0.0..165.0);
-        let z = rng.gen_range(0.0..165.0);
-        let white = Lambertian::new(Color::new(0.73, 0.73, 0.73));
-        boxes2.add(Arc::new(Sphere::new(Point3::new(x, y, z), 10.0, white)));
+/// Builds a built-in scene, or reads a scene file with the shutter open
+/// over `shutter` if given.
+fn load_scene(scene_name: &str, seed: u64, shutter: Option<(f64, f64)>) -> Scene {
+    let mut scene_rng = Pcg32::seed_from_u64(seed);
+    // Textures draw from the thread's generator while the scene is built
+    random::seed(seed);
+    if scene_name.ends_with(".toml") {
+        let path = Path::new(scene_name);
+        let scene = match shutter {
+            Some((time0, time1)) => Scene::load_at(path, time0, time1),
+            None => Scene::load(path),
+        };
+        match scene {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        match scenes::builtin_scene(scene_name, &mut scene_rng) {
+            Some(scene) => scene,
+            None => {
+                eprintln!(
+                    "unknown scene \"{}\", expected a .toml file or one of: {}",
+                    scene_name,
+                    scenes::BUILTIN_SCENES.join(", ")
+                );
+                std::process::exit(2);
+            }
+        }
     }
-
-    let bvh2 = BVHNode::new_boxed(boxes2, 0.0, 1.0);
-    world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(bvh2, 15.0)),
-        Vec3::new(-100.0, 270.0, 395.0),
-    )));
-
-    world
 }
-fn main() {
-    let now = Instant::now();
 
-    // get environment variable CI, which is true for GitHub Actions
+/// Renders one image of `scene` to `path`, carrying on from `resumed`.
+/// Setting `stop` cuts the render short.
+fn render(
+    args: &Args,
+    scene: Scene,
+    scene_name: &str,
+    seed: u64,
+    resumed: Option<Checkpoint>,
+    path: &Path,
+    stop: &Arc<AtomicBool>,
+) {
     let is_ci: bool = is_ci();
-
-    println!("CI: {}", is_ci);
-
-    let mut aspect_ratio: f64 = 16.0 / 9.0;
-    let mut width = 400;
-    let mut height = 225;
-    let path: &str = "output/test.jpg";
-    let quality: u8 = 60; // From 0 to 100, suggested value: 60
-    let mut samples_per_pixel: u64 = 200;
-    let max_depth = 50;
-    // Create image data
-
-    let world_scene;
-
-    let lookfrom;
-    let lookat;
-    let vfov;
-    let mut aperture: f64 = 0.0;
-    let background;
-
-    match 0 {
-        1 => {
-            world_scene = random_scene();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-        2 => {
-            world_scene = two_speres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        3 => {
-            world_scene = two_perlin_spheres();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-        }
-        4 => {
-            world_scene = earth_scene();
-            background = Color::new(0.70, 0.80, 1.00);
-            lookfrom = Point3::new(13.0, 2.0, 3.0);
-            lookat = Point3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
+    let format = args.output_format().unwrap();
+    let quality: u8 = args.quality; // From 0 to 100, suggested value: 60
+    let Scene {
+        world: world_scene,
+        lights,
+        camera: mut cam,
+        background,
+        mut width,
+        mut height,
+        mut samples_per_pixel,
+        mut max_depth,
+        animation: _,
+    } = scene;
+
+    // Command-line settings override the scene defaults
+    let aspect_ratio = width as f64 / height as f64;
+    match (args.width, args.height) {
+        (Some(w), Some(h)) => {
+            width = w;
+            height = h;
+            cam.set_aspect_ratio(width as f64 / height as f64);
         }
-        5 => {
-            world_scene = simple_light();
-            samples_per_pixel = 400;
-            background = Color::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(26.0, 3.0, 6.0);
-            lookat = Point3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
+        (Some(w), None) => {
+            width = w;
+            height = ((w as f64 / aspect_ratio).round() as usize).max(1);
         }
-        6 => {
-            world_scene = cornell_box();
-            aspect_ratio = 1.0;
-            width = 600;
-            height = 600;
-            samples_per_pixel = 200;
-            background = Color::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-        }
-        7 => {
-            world_scene = cornell_smoke();
-            aspect_ratio = 1.0;
-            width = 600;
-            height = 600;
-            samples_per_pixel = 200;
-            background = Color::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(278.0, 278.0, -800.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-        }
-        _ => {
-            world_scene = final_scene();
-            aspect_ratio = 1.0;
-            width = 800; //800
-            height = 800; //800
-            samples_per_pixel = 100; //10000
-            background = Color::new(0.0, 0.0, 0.0);
-            lookfrom = Point3::new(478.0, 278.0, -600.0);
-            lookat = Point3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
+        (None, Some(h)) => {
+            height = h;
+            width = ((h as f64 * aspect_ratio).round() as usize).max(1);
         }
+        (None, None) => {}
     }
-
-    let img = Arc::new(Mutex::new(ImageBuffer::new(
-        width.try_into().unwrap(),
-        height.try_into().unwrap(),
-    )));
-
-    let world: Arc<dyn Hittable + Send + Sync> = BVHNode::new_boxed(world_scene, 0.0, 1.0);
-
-    let vup: Vec3 = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus: f64 = 10.0;
-    let cam: Camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0,
-    );
-
-    // Progress bar UI powered by library `indicatif`
-    // You can use indicatif::ProgressStyle to make it more beautiful
-    // You can also use indicatif::MultiProgress in multi-threading to show progress of each thread
-    let bar: Arc<ProgressBar> = if is_ci {
-        Arc::new(ProgressBar::hidden())
-    } else {
-        Arc::new(ProgressBar::new((height * width) as u64))
-    };
-
-    // let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
-
-    let mut handles = vec![];
-    let thread_num = 55;
-    println!("使用{}条线程渲染", thread_num);
-
-    for k in 0..thread_num {
-        let world = world.clone();
-        let img = img.clone();
-        let bar = bar.clone();
-        let background_ = background;
-        let cam_ = cam;
-
-        let handle = thread::spawn(move || {
-            for j in (k * width / thread_num)..((k + 1) * width / thread_num) {
-                for i in 0..width {
-                    let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
-
-                    let mut pixel_c: Color = Color::new(0.0, 0.0, 0.0);
-                    for _ in 0..samples_per_pixel {
-                        let u_rand: f64 = rng.gen();
-                        let v_rand: f64 = rng.gen();
-                        let u: f64 = (i as f64 + u_rand) / (width as f64 - 1.0);
-                        let v: f64 = (j as f64 + v_rand) / (height as f64 - 1.0);
-                        let r: Ray = cam_.get_ray(u, v);
-                        pixel_c += ray_color(r, background_, &*world, max_depth);
-                    }
-                    let pixel_color: [u8; 3] = [
-                        (clamp(
-                            (pixel_c.x() * 1.0 / samples_per_pixel as f64).sqrt(),
-                            0.0,
-                            0.999,
-                        ) * 255.)
-                            .floor() as u8,
-                        (clamp(
-                            (pixel_c.y() * 1.0 / samples_per_pixel as f64).sqrt(),
-                            0.0,
-                            0.999,
-                        ) * 255.)
-                            .floor() as u8,
-                        (clamp(
-                            (pixel_c.z() * 1.0 / samples_per_pixel as f64).sqrt(),
-                            0.0,
-                            0.999,
-                        ) * 255.)
-                            .floor() as u8,
-                    ];
-                    write_color(pixel_color, &mut img.lock().unwrap(), i, height - j - 1);
-                    bar.inc(1);
-                }
-            }
-        });
-        handles.push(handle);
+    samples_per_pixel = args.samples_per_pixel.unwrap_or(samples_per_pixel);
+    max_depth = args.max_depth.unwrap_or(max_depth);
+
+    // Everything that changes what a sample looks like. The sample count is
+    // left out so a resumed render may be given more of them.
+    let mut settings: Vec<u8> = format!(
+        "{} {}x{} depth {} {:?} {:?} {:?} adaptive {:?} {:?} {:?} seed {}",
+        scene_name,
+        width,
+        height,
+        max_depth,
+        args.integrator,
+        args.sampler,
+        args.filter,
+        args.adaptive,
+        args.min_samples,
+        args.max_samples,
+        seed
+    )
+    .into_bytes();
+    // A scene file may have been edited since under the same name
+    if scene_name.ends_with(".toml") {
+        settings.extend(std::fs::read(scene_name).unwrap_or_default());
     }
-
-    for handle in handles {
-        handle.join().unwrap();
+    let settings_hash: u64 = checkpoint::settings_hash(&settings);
+    if let Some(c) = &resumed {
+        if c.settings_hash != settings_hash
+            || (c.framebuffer.width, c.framebuffer.height) != (width, height)
+        {
+            eprintln!("The checkpoint was made with different scene or render settings");
+            std::process::exit(2);
+        }
+        println!("Resuming after {} samples per pixel", c.progress.samples);
     }
 
-    // Finish progress bar
-    bar.finish();
-
-    // Output image to file
-    println!("Ouput image as \"{}\"\n Author: {}", path, AUTHOR);
-    // let output_image: image::DynamicImage = image::DynamicImage::ImageRgb8(img);
-    let output_image =
-        image::DynamicImage::ImageRgb8(Mutex::into_inner(Arc::into_inner(img).unwrap()).unwrap());
-    let mut output_file: File = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("Outputting image fails."),
+    let mut objects: Vec<Arc<dyn Hittable + Send + Sync>> = world_scene.hittable_list;
+    if args.aovs.contains(&Aov::ObjectId) {
+        // Objects are numbered in the order the scene lists them
+        objects = objects
+            .into_iter()
+            .enumerate()
+            .map(|(i, object)| {
+                Arc::new(Tagged::new(object, i as u32 + 1)) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect();
     }
-    let end = now.elapsed().as_secs();
-    println!("程序运行了 {} 秒", end);
-}
-
-fn ray_color(r: Ray, background: Color, world: &dyn Hittable, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+    let (time0, time1) = cam.shutter();
+    let (world, bvh_stats) = LinearBVH::build_with(objects, time0, time1, args.bvh);
+    let world: Arc<dyn Hittable + Send + Sync> = Arc::new(world);
+    if args.bvh_stats {
+        println!("{}", bvh_stats);
     }
 
-    if let Some(hit_rec) = world.hit(&r, 0.001, f64::INFINITY) {
-        let emitted: Color = hit_rec.mat_ptr.emitted(hit_rec.u, hit_rec.v, &hit_rec.p);
-        let tmp_rec = hit_rec.clone();
-        if let Some((scattered, attenuation)) = hit_rec.mat_ptr.scatter(&r, &tmp_rec) {
-            emitted + attenuation * ray_color(scattered, background, world, depth - 1)
-        } else {
-            emitted
+    let mut renderer = Renderer::new(width, height);
+    renderer.samples_per_pixel = samples_per_pixel;
+    renderer.max_depth = max_depth;
+    renderer.background = background;
+    renderer.lights = lights;
+    renderer.integrator = args.integrator;
+    renderer.sampler = args.sampler;
+    renderer.filter = args.filter;
+    renderer.features = args.denoise || args.aovs.iter().any(|aov| aov.needs_first_hit());
+    renderer.threads = args.threads.unwrap_or(renderer.threads);
+    renderer.tile_size = args.tile_size;
+    renderer.tile_order = args.tile_order;
+    renderer.show_progress = !is_ci;
+    renderer.seed = seed;
+    renderer.stop = stop.clone();
+    renderer.adaptive = args.adaptive.map(|threshold| AdaptiveSampling {
+        threshold,
+        min_samples: args.min_samples.unwrap_or(16).min(samples_per_pixel),
+        max_samples: args.max_samples.unwrap_or(8 * samples_per_pixel),
+    });
+    println!("使用{}条线程渲染", renderer.threads);
+    // Output image to file after every pass, so long renders can be
+    // inspected and stopped early
+    println!("Ouput image as \"{}\"\n Author: {}", path.display(), AUTHOR);
+    let write = |fb: &Framebuffer, path: &Path| {
+        let denoised: Option<Framebuffer> = args.denoise.then(|| Denoiser::default().denoise(fb));
+        let image: &Framebuffer = denoised.as_ref().unwrap_or(fb);
+        if let Err(e) = output::write_image(image, path, format, quality, &args.tone_mapper) {
+            eprintln!("Outputting image fails: {}", e);
+            std::process::exit(1);
         }
-    } else {
-        background
-    }
-}
-
-pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
-    if x < min {
-        return min;
+        for &aov in &args.aovs {
+            let aov_path = output::aov_path(path, aov);
+            if let Err(e) =
+                output::write_aov(fb, aov, &aov_path, format, quality, &args.tone_mapper)
+            {
+                eprintln!("Outputting the {} pass fails: {}", aov.name(), e);
+                std::process::exit(1);
+            }
+        }
+        if let Some(heatmap) = &args.heatmap {
+            if let Err(e) = output::sample_heatmap(fb).save(heatmap) {
+                eprintln!("Outputting heat map fails: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let save = |framebuffer: &Framebuffer, progress: Progress, path: &str| {
+        let checkpoint = Checkpoint {
+            settings_hash,
+            seed,
+            progress,
+            framebuffer: framebuffer.clone(),
+        };
+        if let Err(e) = checkpoint.save(Path::new(path)) {
+            eprintln!("Saving checkpoint fails: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let (framebuffer, progress) = match resumed {
+        Some(c) => (c.framebuffer, c.progress),
+        None => (Framebuffer::new(width, height), Progress::default()),
+    };
+    let checkpoint_every = Duration::from_secs(args.checkpoint_every);
+    let mut last_checkpoint = Instant::now();
+    // Adaptive sampling needs passes to find out where the noise is, and
+    // checkpoints can only be taken between passes
+    let pass_samples = args.pass_samples.unwrap_or(match renderer.adaptive {
+        Some(adaptive) => adaptive.min_samples.max(1),
+        None if args.checkpoint.is_some() => samples_per_pixel.min(16),
+        None => samples_per_pixel,
+    });
+    let (framebuffer, progress) = renderer.resume_progressive(
+        world,
+        &cam,
+        pass_samples,
+        framebuffer,
+        progress,
+        |progress, fb| {
+            write(fb, path);
+            if args.previews {
+                write(fb, &output::numbered_path(path, progress.passes));
+            }
+            if let Some(checkpoint) = &args.checkpoint {
+                if last_checkpoint.elapsed() >= checkpoint_every {
+                    save(fb, progress, checkpoint);
+                    last_checkpoint = Instant::now();
+                }
+            }
+            true
+        },
+    );
+    if let Some(checkpoint) = &args.checkpoint {
+        save(&framebuffer, progress, checkpoint);
+        println!(
+            "Checkpoint with {} samples per pixel saved as \"{}\"",
+            progress.samples, checkpoint
+        );
     }
-    if x > max {
-        return max;
+    if renderer.stop.load(Ordering::Relaxed) {
+        eprintln!("Interrupted, continue with `--resume`");
+        std::process::exit(130);
     }
-    x
 }
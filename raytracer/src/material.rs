@@ -1,17 +1,95 @@
 use std::sync::Arc;
 
 use crate::hittable::HitRecord;
+use crate::pdf::{CosinePdf, FuzzPdf, Pdf};
+use crate::random;
 use crate::ray::Ray;
 use crate::texture::{SolidColor, Texture};
 use crate::vec3::Vec3;
 use crate::vec3::{Color, Point3};
 use rand::Rng;
 
+/// How a ray leaves a surface: a specular ray to follow as is, or a
+/// density of diffuse directions to sample.
+pub struct ScatterRecord {
+    pub specular_ray: Option<Ray>,
+    pub attenuation: Color,
+    pub pdf_ptr: Option<Box<dyn Pdf>>,
+}
+
 pub trait Material: Send + Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
+    /// The surface color without lighting, a guide for the denoiser.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+    /// The number of the material for the material ID pass, 0 for none.
+    fn id(&self) -> u32 {
+        0
+    }
+}
+
+impl<M: Material + ?Sized> Material for Arc<M> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        (**self).scatter(r_in, rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        (**self).scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        (**self).emitted(u, v, p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        (**self).albedo(rec)
+    }
+
+    fn id(&self) -> u32 {
+        (**self).id()
+    }
+}
+
+/// Gives a material its number, for the material ID pass. Scene files
+/// number theirs by name; built-in scenes and MTL files leave theirs at 0.
+pub struct Numbered {
+    pub ptr: Arc<dyn Material + Send + Sync>,
+    pub id: u32,
+}
+
+impl Numbered {
+    pub fn new(p: Arc<dyn Material + Send + Sync>, id: u32) -> Self {
+        Self { ptr: p, id }
+    }
+}
+
+impl Material for Numbered {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.ptr.scatter(r_in, rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.ptr.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.ptr.emitted(u, v, p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.ptr.albedo(rec)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 pub struct Lambertian {
@@ -31,14 +109,25 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_direction: Vec3 = rec.normal + Vec3::random_unit_vector();
-        if Vec3::near_zero(&scatter_direction) {
-            scatter_direction = rec.normal;
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf_ptr: Some(Box::new(CosinePdf::new(rec.normal))),
+        })
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine: f64 = Vec3::dot(rec.normal, Vec3::unit_vector(scattered.dir));
+        if cosine < 0.0 {
+            0.0
+        } else {
+            cosine / std::f64::consts::PI
         }
-        let attenuation: Color = self.albedo.value(rec.u, rec.v, &rec.p);
-        let scattered: Ray = Ray::new(rec.p, scatter_direction, r_in.tm);
-        Some((scattered, attenuation))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
     }
 }
 
@@ -57,20 +146,40 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    /// A perfect mirror reflects specularly. Fuzzy metal has a lobe of
+    /// directions around the mirror one, which light sampling can be
+    /// weighted against; those below the surface are absorbed.
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected: Vec3 = Vec3::reflect(Vec3::unit_vector(r_in.dir), rec.normal);
-        let scattered: Ray = Ray::new(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-            r_in.tm,
-        );
-        let attenuation: Color = self.albedo;
-        if Vec3::dot(scattered.dir, rec.normal) > 0.0 {
-            Some((scattered, attenuation))
+        if self.fuzz > 0.0 {
+            return Some(ScatterRecord {
+                specular_ray: None,
+                attenuation: self.albedo,
+                pdf_ptr: Some(Box::new(FuzzPdf::new(reflected, self.fuzz))),
+            });
+        }
+        if Vec3::dot(reflected, rec.normal) > 0.0 {
+            Some(ScatterRecord {
+                specular_ray: Some(Ray::new(rec.p, reflected, r_in.tm)),
+                attenuation: self.albedo,
+                pdf_ptr: None,
+            })
         } else {
             None
         }
     }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        if Vec3::dot(scattered.dir, rec.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected: Vec3 = Vec3::reflect(Vec3::unit_vector(r_in.dir), rec.normal);
+        FuzzPdf::new(reflected, self.fuzz).value(scattered.dir)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Dielectric {
@@ -90,7 +199,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let attenuation: Color = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio: f64 = if rec.front_face {
             1.0 / self.ir
@@ -102,7 +211,7 @@ impl Material for Dielectric {
         let sin_theta: f64 = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
         let direction: Vec3 = if cannot_refract
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen()
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > random::rng().gen()
         {
             Vec3::reflect(unit_direction, rec.normal)
         } else {
@@ -110,7 +219,11 @@ impl Material for Dielectric {
         };
 
         let scattered: Ray = Ray::new(rec.p, direction, r_in.tm);
-        Some((scattered, attenuation))
+        Some(ScatterRecord {
+            specular_ray: Some(scattered),
+            attenuation,
+            pdf_ptr: None,
+        })
     }
 }
 
@@ -119,9 +232,9 @@ pub struct DiffuseLight {
 }
 
 impl DiffuseLight {
-    // pub fn new(a: Arc<dyn Texture + Send + Sync>) -> Self {
-    //     Self { emit: a }
-    // }
+    pub fn new(a: Arc<dyn Texture + Send + Sync>) -> Self {
+        Self { emit: a }
+    }
 
     pub fn new_col(c: Color) -> Self {
         Self {
@@ -131,7 +244,7 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Ray, Color)> {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
         None
     }
 
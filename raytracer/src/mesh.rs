@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::{LinearBVH, SplitMethod};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::triangle;
+use crate::vec3::{Point3, Vec3};
+
+/// One face of a mesh, as indices into the shared vertex buffers. Positions,
+/// normals and texture coordinates are indexed separately, the way OBJ does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+/// Vertex buffers shared by every triangle of a mesh.
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<MeshFace>,
+    pub mp: Arc<dyn Material + Send + Sync>,
+}
+
+impl MeshData {
+    fn vertices(&self, face: &MeshFace) -> [Point3; 3] {
+        face.positions.map(|i| self.positions[i])
+    }
+}
+
+/// A single face of a mesh, referring back to the shared buffers.
+pub struct MeshTriangle {
+    pub mesh: Arc<MeshData>,
+    pub face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let face: &MeshFace = &self.mesh.faces[self.face];
+        let p: [Point3; 3] = self.mesh.vertices(face);
+        let (t, b1, b2) = triangle::intersect(&p, r, t_min, t_max)?;
+        let normals: Option<[Vec3; 3]> = face.normals.map(|n| n.map(|i| self.mesh.normals[i]));
+        let uvs: [(f64, f64); 3] = match face.uvs {
+            Some(uv) => uv.map(|i| self.mesh.uvs[i]),
+            None => triangle::DEFAULT_UVS,
+        };
+        Some(triangle::hit_record(
+            &p,
+            normals,
+            &uvs,
+            &*self.mesh.mp,
+            r,
+            t,
+            b1,
+            b2,
+        ))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        let face: &MeshFace = &self.mesh.faces[self.face];
+        Some(triangle::bounding_box(&self.mesh.vertices(face)))
+    }
+}
+
+/// A triangle mesh with its own BVH over its faces, so it can be placed in a
+/// scene as a single object.
+pub struct TriangleMesh {
+    pub data: Arc<MeshData>,
+    bvh: Option<LinearBVH>,
+}
+
+impl TriangleMesh {
+    /// Panics if a face refers to a vertex, normal or texture coordinate
+    /// that is not in the buffers.
+    pub fn new(data: MeshData) -> Self {
+        for face in &data.faces {
+            assert!(
+                face.positions.iter().all(|&i| i < data.positions.len()),
+                "mesh face refers to a missing position"
+            );
+            if let Some(n) = face.normals {
+                assert!(
+                    n.iter().all(|&i| i < data.normals.len()),
+                    "mesh face refers to a missing normal"
+                );
+            }
+            if let Some(uv) = face.uvs {
+                assert!(
+                    uv.iter().all(|&i| i < data.uvs.len()),
+                    "mesh face refers to a missing texture coordinate"
+                );
+            }
+        }
+        let data: Arc<MeshData> = Arc::new(data);
+        let triangles: Vec<Arc<dyn Hittable + Send + Sync>> = (0..data.faces.len())
+            .map(|face| {
+                Arc::new(MeshTriangle {
+                    mesh: data.clone(),
+                    face,
+                }) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect();
+        let bvh = if triangles.is_empty() {
+            None
+        } else {
+            Some(LinearBVH::build_with(triangles, 0.0, 1.0, SplitMethod::Sah).0)
+        };
+        Self { data, bvh }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.faces.is_empty()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.as_ref()?.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.bvh.as_ref()?.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::Color;
+
+    fn quad() -> TriangleMesh {
+        // A unit square in the z = 0 plane, facing +z
+        TriangleMesh::new(MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            faces: vec![
+                MeshFace {
+                    positions: [0, 1, 2],
+                    normals: None,
+                    uvs: Some([0, 1, 2]),
+                },
+                MeshFace {
+                    positions: [0, 2, 3],
+                    normals: None,
+                    uvs: Some([0, 2, 3]),
+                },
+            ],
+            mp: Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        })
+    }
+
+    #[test]
+    fn test_mesh_hit() {
+        let mesh = quad();
+        let r = Ray::new(Point3::new(0.25, 0.75, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = mesh.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        assert!((rec.u - 0.25).abs() < 1e-9);
+        assert!((rec.v - 0.75).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!((rec.normal.z() - 1.0).abs() < 1e-9);
+
+        let miss = Ray::new(Point3::new(1.5, 0.5, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(mesh.hit(&miss, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_shading_normals() {
+        let n = Vec3::unit_vector(Vec3::new(1.0, 0.0, 1.0));
+        let tri = triangle::Triangle::new_with_attributes(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            // Deliberately flipped: they are turned to the geometric side
+            Some([-n, -n, -n]),
+            triangle::DEFAULT_UVS,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let r = Ray::new(Point3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = tri.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+        assert!((rec.normal - n).length() < 1e-9);
+    }
+}
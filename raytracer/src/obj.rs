@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::mesh::{MeshData, MeshFace, TriangleMesh};
+use crate::texture::ImageTexture;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// Why a Wavefront OBJ or MTL file could not be loaded.
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Texture {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, source } => {
+                write!(f, "cannot open image \"{}\": {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Texture { source, .. } => Some(source),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Splits a line into its keyword and arguments, dropping comments.
+fn tokens(line: &str) -> Option<(&str, Vec<&str>)> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut words = line.split_whitespace();
+    let keyword = words.next()?;
+    Some((keyword, words.collect()))
+}
+
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message,
+        }
+    }
+
+    fn floats<const N: usize>(
+        &self,
+        keyword: &str,
+        args: &[&str],
+        min: usize,
+    ) -> Result<[f64; N], ObjError> {
+        if args.len() < min {
+            return Err(self.error(format!("`{}` needs at least {} numbers", keyword, min)));
+        }
+        let mut values: [f64; N] = [0.0; N];
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = arg
+                .parse()
+                .map_err(|_| self.error(format!("invalid number \"{}\" in `{}`", arg, keyword)))?;
+        }
+        Ok(values)
+    }
+
+    fn float(&self, keyword: &str, args: &[&str]) -> Result<f64, ObjError> {
+        Ok(self.floats::<1>(keyword, args, 1)?[0])
+    }
+
+    /// Resolves a 1-based or negative (relative) OBJ index into `0..len`.
+    fn index(&self, word: &str, len: usize, what: &str) -> Result<usize, ObjError> {
+        let i: i64 = word
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} index \"{}\"", what, word)))?;
+        let resolved: i64 = if i < 0 { len as i64 + i } else { i - 1 };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!(
+                "{} index {} is out of range, {} defined so far",
+                what, i, len
+            )));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+/// Material properties from an MTL file, before they are mapped onto one of
+/// our materials.
+struct MtlDesc {
+    kd: Color,
+    map_kd: Option<PathBuf>,
+    ks: Color,
+    ns: f64,
+    ni: f64,
+    d: f64,
+    ke: Color,
+    illum: i32,
+}
+
+impl Default for MtlDesc {
+    fn default() -> Self {
+        Self {
+            kd: Color::new(0.8, 0.8, 0.8),
+            map_kd: None,
+            ks: Color::new(0.0, 0.0, 0.0),
+            ns: 0.0,
+            ni: 1.5,
+            d: 1.0,
+            ke: Color::new(0.0, 0.0, 0.0),
+            illum: 2,
+        }
+    }
+}
+
+fn max_component(c: Color) -> f64 {
+    c.x().max(c.y()).max(c.z())
+}
+
+impl MtlDesc {
+    /// Emissive materials become lights, transparent ones glass, mostly
+    /// specular ones metal and everything else is diffuse. The metal's fuzz
+    /// is the roughness matching the Phong exponent `Ns`.
+    fn build(&self) -> Result<Arc<dyn Material + Send + Sync>, ObjError> {
+        if max_component(self.ke) > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new_col(self.ke)));
+        }
+        if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            return Ok(Arc::new(Dielectric::new(self.ni)));
+        }
+        if max_component(self.ks) > 0.0
+            && (max_component(self.ks) >= max_component(self.kd) || self.illum == 3)
+        {
+            let fuzz: f64 = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt().min(1.0);
+            return Ok(Arc::new(Metal::new(self.ks, fuzz)));
+        }
+        match &self.map_kd {
+            Some(path) => match ImageTexture::open(&path.to_string_lossy()) {
+                Ok(texture) => Ok(Arc::new(Lambertian::new_arc(Arc::new(texture)))),
+                Err(source) => Err(ObjError::Texture {
+                    path: path.clone(),
+                    source,
+                }),
+            },
+            None => Ok(Arc::new(Lambertian::new(self.kd))),
+        }
+    }
+}
+
+/// Reads the materials of an MTL file. Texture paths are relative to it.
+fn load_mtl(
+    path: &Path,
+    materials: &mut HashMap<String, Arc<dyn Material + Send + Sync>>,
+) -> Result<(), ObjError> {
+    let src = read(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut parser = LineParser { path, line: 0 };
+    let mut current: Option<(String, MtlDesc)> = None;
+    for (i, line) in src.lines().enumerate() {
+        parser.line = i + 1;
+        let (keyword, args) = match tokens(line) {
+            Some(t) => t,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            if let Some((name, desc)) = current.take() {
+                materials.insert(name, desc.build()?);
+            }
+            current = Some((args.join(" "), MtlDesc::default()));
+            continue;
+        }
+        let desc = match current.as_mut() {
+            Some((_, desc)) => desc,
+            None => return Err(parser.error(format!("`{}` before `newmtl`", keyword))),
+        };
+        let color = |c: [f64; 3]| Color::new(c[0], c[1], c[2]);
+        match keyword {
+            "Kd" => desc.kd = color(parser.floats(keyword, &args, 3)?),
+            "Ks" => desc.ks = color(parser.floats(keyword, &args, 3)?),
+            "Ke" => desc.ke = color(parser.floats(keyword, &args, 3)?),
+            "Ns" => desc.ns = parser.float(keyword, &args)?,
+            "Ni" => desc.ni = parser.float(keyword, &args)?,
+            "d" => desc.d = parser.float(keyword, &args)?,
+            "Tr" => desc.d = 1.0 - parser.float(keyword, &args)?,
+            "illum" => desc.illum = parser.float(keyword, &args)? as i32,
+            // Texture options come before the file name, which is last
+            "map_Kd" => match args.last() {
+                Some(file) => desc.map_kd = Some(dir.join(file)),
+                None => return Err(parser.error(String::from("`map_Kd` needs a file name"))),
+            },
+            _ => {}
+        }
+    }
+    if let Some((name, desc)) = current {
+        materials.insert(name, desc.build()?);
+    }
+    Ok(())
+}
+
+/// Faces sharing one material, with indices into the whole file's buffers.
+struct Group {
+    material: Arc<dyn Material + Send + Sync>,
+    faces: Vec<MeshFace>,
+}
+
+/// Copies the vertices used by `faces` into buffers of their own.
+fn compact<T: Copy>(
+    data: &[T],
+    faces: &mut [MeshFace],
+    get: impl Fn(&mut MeshFace) -> Option<&mut [usize; 3]>,
+) -> Vec<T> {
+    let mut remap: HashMap<usize, usize> = HashMap::new();
+    let mut out: Vec<T> = Vec::new();
+    for face in faces.iter_mut() {
+        if let Some(indices) = get(face) {
+            for i in indices.iter_mut() {
+                *i = *remap.entry(*i).or_insert_with(|| {
+                    out.push(data[*i]);
+                    out.len() - 1
+                });
+            }
+        }
+    }
+    out
+}
+
+/// Loads the triangles of a Wavefront OBJ file, one mesh per material.
+/// Materials come from the file's MTL libraries unless `material` is given,
+/// in which case it is used for the whole model.
+pub fn load_obj(
+    path: &Path,
+    material: Option<Arc<dyn Material + Send + Sync>>,
+) -> Result<Vec<TriangleMesh>, ObjError> {
+    let src = read(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut parser = LineParser { path, line: 0 };
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f64, f64)> = Vec::new();
+    let mut materials: HashMap<String, Arc<dyn Material + Send + Sync>> = HashMap::new();
+    let default_material: Arc<dyn Material + Send + Sync> = material
+        .clone()
+        .unwrap_or_else(|| Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))));
+    let mut groups: Vec<Group> = vec![Group {
+        material: default_material,
+        faces: Vec::new(),
+    }];
+    let mut group_of: HashMap<String, usize> = HashMap::new();
+    let mut current: usize = 0;
+
+    for (i, line) in src.lines().enumerate() {
+        parser.line = i + 1;
+        let (keyword, args) = match tokens(line) {
+            Some(t) => t,
+            None => continue,
+        };
+        match keyword {
+            "v" => {
+                let [x, y, z] = parser.floats(keyword, &args, 3)?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = parser.floats(keyword, &args, 3)?;
+                normals.push(Vec3::unit_vector(Vec3::new(x, y, z)));
+            }
+            "vt" => {
+                let [u, v] = parser.floats(keyword, &args, 1)?;
+                uvs.push((u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parser.error(String::from("a face needs at least 3 vertices")));
+                }
+                let mut corners: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+                for arg in &args {
+                    let mut parts = arg.split('/');
+                    let p = parser.index(
+                        parts.next().unwrap_or_default(),
+                        positions.len(),
+                        "vertex",
+                    )?;
+                    let t = match parts.next() {
+                        Some(w) if !w.is_empty() => Some(parser.index(w, uvs.len(), "texture")?),
+                        _ => None,
+                    };
+                    let n = match parts.next() {
+                        Some(w) if !w.is_empty() => {
+                            Some(parser.index(w, normals.len(), "normal")?)
+                        }
+                        _ => None,
+                    };
+                    corners.push((p, t, n));
+                }
+                // Polygons are split into a fan around their first vertex
+                for k in 1..corners.len() - 1 {
+                    let c: [(usize, Option<usize>, Option<usize>); 3] =
+                        [corners[0], corners[k], corners[k + 1]];
+                    let all_uvs = c.iter().all(|v| v.1.is_some());
+                    let all_normals = c.iter().all(|v| v.2.is_some());
+                    groups[current].faces.push(MeshFace {
+                        positions: c.map(|v| v.0),
+                        uvs: all_uvs.then(|| c.map(|v| v.1.unwrap())),
+                        normals: all_normals.then(|| c.map(|v| v.2.unwrap())),
+                    });
+                }
+            }
+            "mtllib" if material.is_none() => {
+                for file in &args {
+                    load_mtl(&dir.join(file), &mut materials)?;
+                }
+            }
+            "usemtl" if material.is_none() => {
+                let name = args.join(" ");
+                current = match group_of.get(&name) {
+                    Some(&g) => g,
+                    None => {
+                        let mat = materials.get(&name).cloned().ok_or_else(|| {
+                            parser.error(format!("unknown material \"{}\"", name))
+                        })?;
+                        groups.push(Group {
+                            material: mat,
+                            faces: Vec::new(),
+                        });
+                        group_of.insert(name, groups.len() - 1);
+                        groups.len() - 1
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+
+    let mut meshes: Vec<TriangleMesh> = Vec::new();
+    for mut group in groups {
+        if group.faces.is_empty() {
+            continue;
+        }
+        let positions = compact(&positions, &mut group.faces, |f| Some(&mut f.positions));
+        let normals = compact(&normals, &mut group.faces, |f| f.normals.as_mut());
+        let uvs = compact(&uvs, &mut group.faces, |f| f.uvs.as_mut());
+        meshes.push(TriangleMesh::new(MeshData {
+            positions,
+            normals,
+            uvs,
+            faces: group.faces,
+            mp: group.material,
+        }));
+    }
+    Ok(meshes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_obj() {
+        write_temp(
+            "quad.mtl",
+            "newmtl white\nKd 0.7 0.7 0.7\n\nnewmtl lamp\nKe 4 4 4\n",
+        );
+        let path = write_temp(
+            "quad.obj",
+            "mtllib quad.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\n\
+             vn 0 0 1\n\
+             usemtl white\n\
+             f 1//1 2//1 3//1 4//1\n\
+             usemtl lamp\n\
+             f -5 -4 -1 # a triangle\n",
+        );
+        let meshes = load_obj(&path, None).unwrap();
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes[0].len(), 2);
+        assert_eq!(meshes[1].len(), 1);
+        // The lamp only uses three of the five vertices
+        assert_eq!(meshes[1].data.positions.len(), 3);
+
+        let r = Ray::new(Point3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let rec = meshes[0].hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!(rec.mat_ptr.emitted(0.0, 0.0, &rec.p).length() == 0.0);
+    }
+
+    #[test]
+    fn test_obj_errors() {
+        let path = write_temp("bad_index.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+        match load_obj(&path, None) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 4),
+            _ => panic!("expected a parse error"),
+        }
+
+        let path = write_temp("no_mtl.obj", "mtllib missing.mtl\n");
+        assert!(matches!(load_obj(&path, None), Err(ObjError::Io { .. })));
+
+        let path = write_temp("bad_number.obj", "v 0 zero 0\n");
+        assert!(load_obj(&path, None)
+            .err()
+            .unwrap()
+            .to_string()
+            .ends_with("bad_number.obj:1: invalid number \"zero\" in `v`"));
+    }
+}
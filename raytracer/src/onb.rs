@@ -0,0 +1,37 @@
+use crate::vec3::Vec3;
+
+/// An orthonormal basis, used to sample directions around a normal.
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn build_from_w(n: Vec3) -> Self {
+        let w: Vec3 = Vec3::unit_vector(n);
+        let a: Vec3 = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v: Vec3 = Vec3::unit_vector(Vec3::cross(w, a));
+        let u: Vec3 = Vec3::cross(w, v);
+        Self { axis: [u, v, w] }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u() + a.y() * self.v() + a.z() * self.w()
+    }
+}
@@ -0,0 +1,329 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use image::codecs::hdr::HdrEncoder;
+use image::{DynamicImage, ImageBuffer, ImageError, ImageOutputFormat, ImageResult, Rgb, RgbImage};
+
+use crate::aov::Aov;
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapper;
+use crate::vec3::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    /// OpenEXR, 32-bit float RGB.
+    Exr,
+    /// Radiance RGBE.
+    Hdr,
+    /// Portable float map, uncompressed 32-bit floats.
+    Pfm,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "png" => Some(OutputFormat::Png),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    /// Picks the format matching the extension of `path`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(OutputFormat::from_name)
+    }
+
+    /// Whether the format stores linear radiance rather than display values.
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm
+        )
+    }
+}
+
+/// `path` with a zero-padded number appended to its file stem, e.g.
+/// `output/test_0003.jpg`.
+pub fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Where frame `frame` of an image sequence goes: `pattern` with a `%d`
+/// or `%0Nd` in it replaced by the frame number, e.g.
+/// `output/frame_%04d.png`, or numbered as by `numbered_path`.
+pub fn frame_path(pattern: &str, frame: usize) -> PathBuf {
+    if let Some(start) = pattern.find('%') {
+        let rest: &str = &pattern[start + 1..];
+        let digits: usize = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if rest[digits..].starts_with('d') {
+            let width: usize = rest[..digits].parse().unwrap_or(0);
+            return PathBuf::from(format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                frame,
+                &rest[digits + 1..],
+                width = width
+            ));
+        }
+    }
+    numbered_path(Path::new(pattern), frame)
+}
+
+/// `path` with the name of a pass put before its extension, e.g.
+/// `output/test.depth.exr`.
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, aov.name(), ext.to_string_lossy()),
+        None => format!("{}.{}", stem, aov.name()),
+    };
+    path.with_file_name(name)
+}
+
+fn rgb32f(values: &[Color]) -> Vec<Rgb<f32>> {
+    values
+        .iter()
+        .map(|c| Rgb([c.x() as f32, c.y() as f32, c.z() as f32]))
+        .collect()
+}
+
+fn radiance(fb: &Framebuffer) -> Vec<Color> {
+    (0..fb.height)
+        .flat_map(|y| (0..fb.width).map(move |x| fb.pixel(x, y)))
+        .collect()
+}
+
+/// Colors of the heat map from the fewest samples to the most.
+const HEAT: [[f64; 3]; 5] = [
+    [0.0, 0.0, 0.0],
+    [0.2, 0.1, 0.6],
+    [0.8, 0.2, 0.4],
+    [1.0, 0.7, 0.1],
+    [1.0, 1.0, 1.0],
+];
+
+/// Shows how many samples each pixel took, from black for none to white
+/// for the most any pixel took.
+pub fn sample_heatmap(fb: &Framebuffer) -> RgbImage {
+    let most: f64 = fb.samples.iter().copied().max().unwrap_or(0).max(1) as f64;
+    ImageBuffer::from_fn(fb.width as u32, fb.height as u32, |x, y| {
+        let n: u32 = fb.samples[y as usize * fb.width + x as usize];
+        let t: f64 = n as f64 / most * (HEAT.len() - 1) as f64;
+        let k: usize = (t as usize).min(HEAT.len() - 2);
+        let f: f64 = t - k as f64;
+        Rgb([0, 1, 2].map(|c| {
+            let v: f64 = HEAT[k][c] + f * (HEAT[k + 1][c] - HEAT[k][c]);
+            (v * 255.0).round() as u8
+        }))
+    })
+}
+
+/// Writes a portable float map. Its rows are stored bottom to top.
+fn write_pfm(
+    width: usize,
+    height: usize,
+    values: &[Color],
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    // A negative scale marks the data as little-endian
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for y in (0..height).rev() {
+        for c in &values[y * width..(y + 1) * width] {
+            for v in [c.x(), c.y(), c.z()] {
+                out.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Saves the framebuffer to `path`. HDR formats get the linear radiance, the
+/// others the 8-bit image produced by `tone_mapper`.
+pub fn write_image(
+    fb: &Framebuffer,
+    path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    tone_mapper: &ToneMapper,
+) -> ImageResult<()> {
+    write_layer(
+        fb,
+        path,
+        format,
+        quality,
+        || radiance(fb),
+        || fb.tone_mapped(tone_mapper),
+    )
+}
+
+/// Saves a pass of the framebuffer to `path`. HDR formats get its values,
+/// the others a picture of them.
+pub fn write_aov(
+    fb: &Framebuffer,
+    aov: Aov,
+    path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    tone_mapper: &ToneMapper,
+) -> ImageResult<()> {
+    write_layer(
+        fb,
+        path,
+        format,
+        quality,
+        || aov.values(fb),
+        || aov.display(fb, tone_mapper),
+    )
+}
+
+fn write_layer(
+    fb: &Framebuffer,
+    path: &Path,
+    format: OutputFormat,
+    quality: u8,
+    values: impl FnOnce() -> Vec<Color>,
+    display: impl FnOnce() -> RgbImage,
+) -> ImageResult<()> {
+    let mut file = BufWriter::new(File::create(path).map_err(ImageError::IoError)?);
+    let written: ImageResult<()> = match format {
+        OutputFormat::Jpeg => {
+            DynamicImage::ImageRgb8(display()).write_to(&mut file, ImageOutputFormat::Jpeg(quality))
+        }
+        OutputFormat::Png => {
+            DynamicImage::ImageRgb8(display()).write_to(&mut file, ImageOutputFormat::Png)
+        }
+        OutputFormat::Exr => {
+            let data: Vec<f32> = rgb32f(&values()).into_iter().flat_map(|p| p.0).collect();
+            let img = image::Rgb32FImage::from_raw(fb.width as u32, fb.height as u32, data)
+                .expect("framebuffer size matches its pixels");
+            DynamicImage::ImageRgb32F(img).write_to(&mut file, ImageOutputFormat::OpenExr)
+        }
+        OutputFormat::Hdr => {
+            HdrEncoder::new(&mut file).encode(&rgb32f(&values()), fb.width, fb.height)
+        }
+        OutputFormat::Pfm => {
+            write_pfm(fb.width, fb.height, &values(), &mut file).map_err(ImageError::IoError)
+        }
+    };
+    written?;
+    // Dropping the writer would flush it but swallow the error
+    file.flush().map_err(ImageError::IoError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::Tile;
+
+    fn bright() -> Framebuffer {
+        let mut fb = Framebuffer::new(3, 2);
+        let mut buffer = crate::framebuffer::TileBuffer::new(Tile {
+            x0: 0,
+            y0: 0,
+            x1: 3,
+            y1: 2,
+        });
+        buffer.add(1, 0, Color::new(15.0, 0.5, 0.25), 1.0);
+        fb.merge(&buffer);
+        fb
+    }
+
+    #[test]
+    fn test_formats_from_path() {
+        assert_eq!(OutputFormat::from_path("a/b.EXR"), Some(OutputFormat::Exr));
+        assert_eq!(OutputFormat::from_path("b.pfm"), Some(OutputFormat::Pfm));
+        assert_eq!(OutputFormat::from_path("b.bmp"), None);
+        assert!(OutputFormat::Hdr.is_hdr() && !OutputFormat::Png.is_hdr());
+        assert_eq!(
+            numbered_path(Path::new("output/test.jpg"), 3),
+            Path::new("output/test_0003.jpg")
+        );
+        assert_eq!(
+            frame_path("output/frame_%04d.png", 12),
+            Path::new("output/frame_0012.png")
+        );
+        assert_eq!(frame_path("shot%d.exr", 7), Path::new("shot7.exr"));
+        assert_eq!(frame_path("shot.jpg", 7), Path::new("shot_0007.jpg"));
+        assert_eq!(
+            aov_path(Path::new("output/test.exr"), Aov::Depth),
+            Path::new("output/test.depth.exr")
+        );
+    }
+
+    #[test]
+    fn test_sample_heatmap() {
+        let mut fb = bright();
+        fb.samples[0] = 4;
+        let heatmap = sample_heatmap(&fb);
+        assert_eq!(heatmap.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(heatmap.get_pixel(1, 0).0, [51, 26, 153]);
+        assert_eq!(heatmap.get_pixel(2, 1).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hdr_round_trip() {
+        let dir = std::env::temp_dir().join(format!("raytracer-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.exr");
+        write_image(
+            &bright(),
+            &path,
+            OutputFormat::Exr,
+            90,
+            &ToneMapper::default(),
+        )
+        .unwrap();
+        let img = image::open(&path).unwrap().to_rgb32f();
+        assert_eq!(img.dimensions(), (3, 2));
+        assert_eq!(img.get_pixel(1, 0).0, [15.0, 0.5, 0.25]);
+
+        let path = dir.join("out.hdr");
+        write_image(
+            &bright(),
+            &path,
+            OutputFormat::Hdr,
+            90,
+            &ToneMapper::default(),
+        )
+        .unwrap();
+        let reader = std::io::BufReader::new(File::open(&path).unwrap());
+        let pixels = image::codecs::hdr::HdrDecoder::new(reader)
+            .unwrap()
+            .read_image_hdr()
+            .unwrap();
+        assert_eq!(pixels.len(), 6);
+        // Values above 1 survive, RGBE only keeps about 8 bits of mantissa
+        assert!((pixels[1].0[0] - 15.0).abs() < 0.1);
+
+        let path = dir.join("out.pfm");
+        write_image(
+            &bright(),
+            &path,
+            OutputFormat::Pfm,
+            90,
+            &ToneMapper::default(),
+        )
+        .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 3 * 2 * 3 * 4);
+        // The top row is stored last
+        let at = header.len() + (3 + 1) * 12;
+        assert_eq!(bytes[at..at + 4], 15.0f32.to_le_bytes());
+    }
+}
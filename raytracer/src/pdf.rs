@@ -0,0 +1,171 @@
+use rand::Rng;
+
+use crate::hittable::Hittable;
+use crate::onb::Onb;
+use crate::random;
+use crate::vec3::{Point3, Vec3};
+
+/// A probability density over directions that can also be sampled.
+pub trait Pdf {
+    fn value(&self, direction: Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+pub struct CosinePdf {
+    pub uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        Self {
+            uvw: Onb::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine: f64 = Vec3::dot(Vec3::unit_vector(direction), self.uvw.w());
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / std::f64::consts::PI
+        }
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.local(Vec3::random_cosine_direction())
+    }
+}
+
+/// Uniform density over the whole sphere of directions.
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * std::f64::consts::PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+}
+
+/// Directions of a fuzzy reflection: the mirror direction `reflected`
+/// plus a uniform point in a ball of radius `fuzz`.
+pub struct FuzzPdf {
+    pub reflected: Vec3,
+    pub fuzz: f64,
+}
+
+impl FuzzPdf {
+    pub fn new(reflected: Vec3, fuzz: f64) -> Self {
+        Self {
+            reflected: Vec3::unit_vector(reflected),
+            fuzz,
+        }
+    }
+}
+
+impl Pdf for FuzzPdf {
+    /// The share of the ball's volume seen in a direction: a ray through
+    /// it from `r1` to `r2` covers `(r2³ - r1³) / 3` of volume per
+    /// steradian.
+    fn value(&self, direction: Vec3) -> f64 {
+        let b: f64 = Vec3::dot(Vec3::unit_vector(direction), self.reflected);
+        let discriminant: f64 = b * b - (1.0 - self.fuzz * self.fuzz);
+        if b <= 0.0 || discriminant <= 0.0 {
+            return 0.0;
+        }
+        let r1: f64 = (b - discriminant.sqrt()).max(0.0);
+        let r2: f64 = b + discriminant.sqrt();
+        (r2.powi(3) - r1.powi(3)) / (4.0 * std::f64::consts::PI * self.fuzz.powi(3))
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.reflected + self.fuzz * Vec3::random_in_unit_sphere()
+    }
+}
+
+/// Density of directions from `o` towards the surface of a hittable as it
+/// is at `time`.
+pub struct HittablePdf<'a> {
+    pub o: Point3,
+    pub time: f64,
+    pub ptr: &'a dyn Hittable,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(ptr: &'a dyn Hittable, origin: Point3, time: f64) -> Self {
+        Self {
+            o: origin,
+            time,
+            ptr,
+        }
+    }
+}
+
+impl<'a> Pdf for HittablePdf<'a> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.ptr.pdf_value(&self.o, &direction, self.time)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.ptr.random(&self.o, self.time)
+    }
+}
+
+/// An even mix of two densities.
+pub struct MixturePdf<'a> {
+    pub p: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p0: &'a dyn Pdf, p1: &'a dyn Pdf) -> Self {
+        Self { p: [p0, p1] }
+    }
+}
+
+impl<'a> Pdf for MixturePdf<'a> {
+    fn value(&self, direction: Vec3) -> f64 {
+        0.5 * self.p[0].value(direction) + 0.5 * self.p[1].value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        if random::rng().gen::<f64>() < 0.5 {
+            self.p[0].generate()
+        } else {
+            self.p[1].generate()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzz_pdf_integrates_to_one() {
+        let pdf = FuzzPdf::new(Vec3::new(0.0, 1.0, 1.0), 0.3);
+        // Midpoint rule over the sphere of directions
+        let (n_theta, n_phi) = (400, 400);
+        let mut total: f64 = 0.0;
+        for i in 0..n_theta {
+            let theta: f64 = (i as f64 + 0.5) / n_theta as f64 * std::f64::consts::PI;
+            for j in 0..n_phi {
+                let phi: f64 = (j as f64 + 0.5) / n_phi as f64 * 2.0 * std::f64::consts::PI;
+                let d = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                total += pdf.value(d) * theta.sin();
+            }
+        }
+        total *= std::f64::consts::PI / n_theta as f64 * 2.0 * std::f64::consts::PI / n_phi as f64;
+        assert!((total - 1.0).abs() < 1e-2, "{}", total);
+        for _ in 0..100 {
+            assert!(pdf.value(pdf.generate()) > 0.0);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 // use crate::texture::Texture;
+use crate::random::{self, SampleRng};
 use crate::vec3::{Point3, Vec3};
 use rand::Rng;
 
@@ -9,11 +10,17 @@ pub struct Perlin {
     pub perm_z: Vec<i32>,
 }
 
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Perlin {
     const POINT_COUNT: i32 = 256;
 
     pub fn new() -> Self {
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
+        let mut rng: SampleRng = random::rng();
 
         let mut ranvec: Vec<Vec3> = Vec::new();
         for _ in 0..Perlin::POINT_COUNT {
@@ -115,7 +122,7 @@ impl Perlin {
     }
 
     fn permute(p: &mut [i32], n: i32) {
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
+        let mut rng: SampleRng = random::rng();
 
         for i in (1..n).rev() {
             let target: i32 = rng.gen_range(0..i);
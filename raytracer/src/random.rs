@@ -0,0 +1,168 @@
+use std::cell::Cell;
+
+use rand::{Error, RngCore, SeedableRng};
+
+/// PCG32 (XSH-RR) by Melissa O'Neill: small, fast, and unlike `StdRng`
+/// fixed, so a seed renders the same image with every build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+impl Pcg32 {
+    /// A generator on one of 2^63 independent `stream`s.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old: u64 = self.state;
+        self.step();
+        let xorshifted: u32 = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let lo: u64 = self.next_u32() as u64;
+        (self.next_u32() as u64) << 32 | lo
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes: [u8; 4] = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Pcg32 {
+    type Seed = [u8; 16];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        let (state, stream) = seed.split_at(8);
+        Pcg32::new(
+            u64::from_le_bytes(state.try_into().unwrap()),
+            u64::from_le_bytes(stream.try_into().unwrap()),
+        )
+    }
+
+    fn seed_from_u64(seed: u64) -> Self {
+        Pcg32::new(seed, 0)
+    }
+}
+
+/// SplitMix64's finalizer, spreading nearby inputs all over the range.
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+thread_local! {
+    static RNG: Cell<Pcg32> = Cell::new(Pcg32::new(0, 0));
+}
+
+/// Handle to the generator of the current thread, used by all sampling
+/// code in place of `rand::thread_rng()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampleRng;
+
+pub fn rng() -> SampleRng {
+    SampleRng
+}
+
+/// Restarts the current thread's generator from `seed`.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| rng.set(Pcg32::new(mix(seed), 0)));
+}
+
+/// Restarts the current thread's generator for sample number `sample` of
+/// pixel `pixel`, so what a sample draws does not depend on which thread
+/// takes it or on what was drawn before.
+pub fn seed_sample(seed: u64, pixel: u64, sample: u64) {
+    RNG.with(|rng| rng.set(Pcg32::new(mix(seed ^ mix(pixel)), sample)));
+}
+
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| {
+            let mut r: Pcg32 = rng.get();
+            let v: u32 = r.next_u32();
+            rng.set(r);
+            v
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| {
+            let mut r: Pcg32 = rng.get();
+            let v: u64 = r.next_u64();
+            rng.set(r);
+            v
+        })
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| {
+            let mut r: Pcg32 = rng.get();
+            r.fill_bytes(dest);
+            rng.set(r);
+        })
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_pcg32_reference_output() {
+        // First outputs of the reference pcg32_random_r with seed 42 on stream 54
+        let mut rng = Pcg32::new(42, 54);
+        let expected: [u32; 6] = [
+            0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e,
+        ];
+        for v in expected {
+            assert_eq!(rng.next_u32(), v);
+        }
+    }
+
+    #[test]
+    fn test_seed_sample_repeats() {
+        seed_sample(7, 3, 1);
+        let a: Vec<f64> = (0..4).map(|_| rng().gen()).collect();
+        seed_sample(7, 3, 2);
+        let b: Vec<f64> = (0..4).map(|_| rng().gen()).collect();
+        seed_sample(7, 3, 1);
+        let c: Vec<f64> = (0..4).map(|_| rng().gen()).collect();
+        assert_eq!(a, c);
+        assert_ne!(a, b);
+    }
+}
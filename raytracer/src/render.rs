@@ -0,0 +1,615 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use image::RgbImage;
+use indicatif::ProgressBar;
+
+use crate::aov::FirstHit;
+use crate::camera::Camera;
+use crate::filter::Filter;
+use crate::framebuffer::{Framebuffer, TileBuffer};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::pdf::{HittablePdf, MixturePdf, Pdf};
+use crate::random;
+use crate::ray::Ray;
+use crate::sampler::{self, SamplerKind};
+use crate::tile::{default_threads, tiles, TileOrder, TileQueue};
+use crate::vec3::{Color, Vec3};
+
+/// How radiance is estimated along a camera ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// Recursive path tracing, sampling a 50/50 mixture of lights and BSDF.
+    Recursive,
+    /// Path tracing with next-event estimation, combining light and BSDF
+    /// samples by multiple importance sampling.
+    Mis(MisHeuristic),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power,
+}
+
+impl MisHeuristic {
+    /// Weight of a sample drawn from a density with `pdf_f`, when `pdf_g` could also have drawn it.
+    pub fn weight(&self, pdf_f: f64, pdf_g: f64) -> f64 {
+        let (f, g) = match self {
+            MisHeuristic::Balance => (pdf_f, pdf_g),
+            MisHeuristic::Power => (pdf_f * pdf_f, pdf_g * pdf_g),
+        };
+        if f + g > 0.0 {
+            f / (f + g)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::str::FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recursive" => Ok(Integrator::Recursive),
+            "mis" | "mis-power" => Ok(Integrator::Mis(MisHeuristic::Power)),
+            "mis-balance" => Ok(Integrator::Mis(MisHeuristic::Balance)),
+            _ => Err(format!("unknown integrator \"{}\"", s)),
+        }
+    }
+}
+
+/// Radiance reaching the camera along a path, split by the number of
+/// bounces it took.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lighting {
+    /// Lights and background seen directly.
+    pub emission: Color,
+    /// Light after one bounce.
+    pub direct: Color,
+    /// Light after two bounces or more.
+    pub indirect: Color,
+}
+
+impl Lighting {
+    pub fn emitted(emission: Color) -> Self {
+        Self {
+            emission,
+            direct: Color::new(0.0, 0.0, 0.0),
+            indirect: Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn total(&self) -> Color {
+        self.emission + self.direct + self.indirect
+    }
+
+    /// Adds `radiance` that took `bounces` bounces to the camera.
+    pub fn add(&mut self, bounces: usize, radiance: Color) {
+        match bounces {
+            0 => self.emission += radiance,
+            1 => self.direct += radiance,
+            _ => self.indirect += radiance,
+        }
+    }
+
+    /// Adds `incoming` light scattered here with weight `attenuation`,
+    /// which takes it one bounce further.
+    pub fn scatter(mut self, attenuation: Color, incoming: Lighting) -> Self {
+        self.direct += attenuation * incoming.emission;
+        self.indirect += attenuation * (incoming.direct + incoming.indirect);
+        self
+    }
+}
+
+/// Renders a world as seen by a camera into an image.
+///
+/// Objects in `lights` are sampled directly at every diffuse bounce. The image
+/// is cut into tiles that `threads` render threads take from a shared queue.
+#[derive(Clone)]
+pub struct Renderer {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u64,
+    pub max_depth: i32,
+    pub background: Color,
+    pub lights: HittableList,
+    pub integrator: Integrator,
+    pub threads: usize,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub show_progress: bool,
+    /// Set to abandon the pass being rendered, e.g. on Ctrl-C.
+    pub stop: Arc<AtomicBool>,
+    /// Every sample draws from a generator seeded with this, its pixel and
+    /// its number, so the image does not depend on the number of threads.
+    pub seed: u64,
+    /// Where the pixel, lens, time and bounce dimensions of samples come from.
+    pub sampler: SamplerKind,
+    /// Spend `samples_per_pixel` on average where the image is noisy rather
+    /// than the same number everywhere.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// How samples are weighted into the pixels around them.
+    pub filter: Filter,
+    /// Record the depth, normal, albedo, texture coordinates and IDs where
+    /// every camera ray first hits, for the passes and the denoiser. Costs
+    /// one more intersection per sample.
+    pub features: bool,
+}
+
+/// Pixels below this mean luminance have their error measured against it.
+const ADAPTIVE_MIN_MEAN: f64 = 0.01;
+
+/// Settings of adaptive sampling. Every pass, pixels whose mean is known
+/// well enough stop taking samples and the others share what is left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Standard error of a pixel's luminance, relative to the luminance,
+    /// below which the pixel is done.
+    pub threshold: f64,
+    /// Samples every pixel takes before its error estimate is trusted.
+    pub min_samples: u64,
+    /// The most samples one pixel may take.
+    pub max_samples: u64,
+}
+
+/// How far a progressive render has got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub passes: usize,
+    /// Samples taken in every pixel so far, on average when sampling
+    /// adaptively.
+    pub samples: u64,
+}
+
+impl Renderer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            samples_per_pixel: 200,
+            max_depth: 50,
+            background: Color::new(0.0, 0.0, 0.0),
+            lights: HittableList::new(),
+            integrator: Integrator::Recursive,
+            threads: default_threads(),
+            tile_size: 16,
+            tile_order: TileOrder::Spiral,
+            show_progress: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            seed: 0,
+            sampler: SamplerKind::Independent,
+            adaptive: None,
+            filter: Filter::default(),
+            features: false,
+        }
+    }
+
+    pub fn render(&self, world: Arc<dyn Hittable + Send + Sync>, cam: &Camera) -> RgbImage {
+        self.render_framebuffer(world, cam).to_rgb8()
+    }
+
+    /// Renders into a floating point framebuffer.
+    pub fn render_framebuffer(
+        &self,
+        world: Arc<dyn Hittable + Send + Sync>,
+        cam: &Camera,
+    ) -> Framebuffer {
+        self.render_progressive(world, cam, self.samples_per_pixel, |_, _| true)
+            .0
+    }
+
+    /// Renders in passes of `pass_samples` samples per pixel until
+    /// `samples_per_pixel` are taken, calling `on_pass` with the progress
+    /// and the accumulated image after each one. Returning `false` from
+    /// `on_pass` stops the render early.
+    pub fn render_progressive(
+        &self,
+        world: Arc<dyn Hittable + Send + Sync>,
+        cam: &Camera,
+        pass_samples: u64,
+        on_pass: impl FnMut(Progress, &Framebuffer) -> bool,
+    ) -> (Framebuffer, Progress) {
+        let framebuffer = Framebuffer::new(self.width, self.height);
+        self.resume_progressive(
+            world,
+            cam,
+            pass_samples,
+            framebuffer,
+            Progress::default(),
+            on_pass,
+        )
+    }
+
+    /// Continues a progressive render from an earlier image and progress.
+    /// Each pixel carries on with its sample sequence where it stopped. A
+    /// pass cut short by `stop` is dropped, so the returned image always
+    /// holds whole passes. A render that is already finished is passed to
+    /// `on_pass` once as it is.
+    pub fn resume_progressive(
+        &self,
+        world: Arc<dyn Hittable + Send + Sync>,
+        cam: &Camera,
+        pass_samples: u64,
+        mut framebuffer: Framebuffer,
+        mut progress: Progress,
+        mut on_pass: impl FnMut(Progress, &Framebuffer) -> bool,
+    ) -> (Framebuffer, Progress) {
+        let pass_samples: u64 = pass_samples.clamp(1, self.samples_per_pixel.max(1));
+        let pixels: u64 = (self.width * self.height) as u64;
+        let budget: u64 = self.samples_per_pixel * pixels;
+
+        // Progress bar UI powered by library `indicatif`
+        // You can use indicatif::ProgressStyle to make it more beautiful
+        // You can also use indicatif::MultiProgress in multi-threading to show progress of each thread
+        let bar: ProgressBar = if self.show_progress {
+            ProgressBar::new(budget.saturating_sub(framebuffer.total_samples()))
+        } else {
+            ProgressBar::hidden()
+        };
+
+        let mut first: bool = true;
+        loop {
+            let plan: Vec<Range<u64>> = self.plan_pass(&framebuffer, pass_samples);
+            if plan.iter().all(|samples| samples.is_empty()) {
+                if first {
+                    on_pass(progress, &framebuffer);
+                }
+                break;
+            }
+            first = false;
+            let mut pass = Framebuffer::new(self.width, self.height);
+            if !self.render_samples(world.clone(), cam, plan, &mut pass, &bar) {
+                break;
+            }
+            framebuffer.accumulate(&pass);
+            progress.passes += 1;
+            progress.samples = framebuffer.total_samples() / pixels.max(1);
+            if !on_pass(progress, &framebuffer) {
+                break;
+            }
+        }
+
+        // Finish progress bar
+        bar.finish();
+
+        (framebuffer, progress)
+    }
+
+    /// The sample numbers each pixel takes in the next pass: up to
+    /// `pass_samples` more until `samples_per_pixel` are taken, or with
+    /// adaptive sampling as many as the pixel still needs while the budget
+    /// of `samples_per_pixel` on average lasts.
+    fn plan_pass(&self, framebuffer: &Framebuffer, pass_samples: u64) -> Vec<Range<u64>> {
+        let taken = |i: usize| framebuffer.samples[i] as u64;
+        let pixels: usize = framebuffer.samples.len();
+        let counts: Vec<u64> = match self.adaptive {
+            None => (0..pixels)
+                .map(|i| pass_samples.min(self.samples_per_pixel.saturating_sub(taken(i))))
+                .collect(),
+            Some(adaptive) => {
+                let active: Vec<bool> = (0..pixels)
+                    .map(|i| {
+                        taken(i) < adaptive.max_samples
+                            && (taken(i) < adaptive.min_samples
+                                || framebuffer.relative_error(i, ADAPTIVE_MIN_MEAN)
+                                    > adaptive.threshold)
+                    })
+                    .collect();
+                let active_pixels: u64 = active.iter().filter(|&&a| a).count() as u64;
+                let remaining: u64 = (self.samples_per_pixel * pixels as u64)
+                    .saturating_sub(framebuffer.total_samples());
+                // Share what is left evenly when it no longer covers a full pass
+                let per_pixel: u64 = pass_samples.min(remaining.div_ceil(active_pixels.max(1)));
+                (0..pixels)
+                    .map(|i| {
+                        if active[i] {
+                            per_pixel.min(adaptive.max_samples - taken(i))
+                        } else {
+                            0
+                        }
+                    })
+                    .collect()
+            }
+        };
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| taken(i)..taken(i) + n)
+            .collect()
+    }
+
+    /// Adds the samples numbered `samples[i]` of every pixel `i` to
+    /// `framebuffer`. Each thread fills its own tile buffers and sends them
+    /// back here to be merged, so no pixel is ever shared between threads.
+    /// Returns `false` if `stop` was set before every tile was done.
+    pub fn render_samples(
+        &self,
+        world: Arc<dyn Hittable + Send + Sync>,
+        cam: &Camera,
+        samples: Vec<Range<u64>>,
+        framebuffer: &mut Framebuffer,
+        bar: &ProgressBar,
+    ) -> bool {
+        let width = self.width;
+        let height = self.height;
+        let max_depth = self.max_depth;
+        let integrator = self.integrator;
+        let seed = self.seed;
+        let sample_source = self.sampler.build(seed, self.samples_per_pixel);
+        let samples = Arc::new(samples);
+        let filter = self.filter;
+        let features = self.features;
+
+        let tiles = Arc::new(TileQueue::new(tiles(
+            width,
+            height,
+            self.tile_size,
+            self.tile_order,
+        )));
+        let (sender, receiver) = mpsc::channel::<TileBuffer>();
+        let mut handles = vec![];
+        let thread_num = self.threads.min(tiles.len()).max(1);
+
+        for _ in 0..thread_num {
+            let world = world.clone();
+            let sender = sender.clone();
+            let tiles = tiles.clone();
+            let background_ = self.background;
+            let lights = self.lights.clone();
+            let cam_ = *cam;
+            let stop = self.stop.clone();
+            let samples = samples.clone();
+            let sample_source = sample_source.clone();
+
+            let handle = thread::spawn(move || {
+                sampler::set_thread_sampler(Some(sample_source));
+                while let Some(tile) = tiles.next() {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let mut buffer = TileBuffer::with_margin(tile, filter.margin(), width, height);
+                    for row in tile.y0..tile.y1 {
+                        for i in tile.x0..tile.x1 {
+                            let pixel: u64 = (row * width + i) as u64;
+                            for sample in samples[pixel as usize].clone() {
+                                random::seed_sample(seed, pixel, sample);
+                                sampler::start_sample(pixel, sample);
+                                // The sample's position in the image, rows down from the top
+                                let (u_rand, v_rand) = sampler::get_2d();
+                                let x: f64 = i as f64 + u_rand;
+                                let y: f64 = row as f64 + v_rand;
+                                let u: f64 = x / width as f64;
+                                let v: f64 = (height as f64 - y) / height as f64;
+                                let r: Ray = cam_.get_ray(u, v);
+                                let sample: Lighting = match integrator {
+                                    Integrator::Recursive => {
+                                        ray_color(r, background_, &*world, &lights, max_depth)
+                                    }
+                                    Integrator::Mis(heuristic) => ray_color_mis(
+                                        r,
+                                        background_,
+                                        &*world,
+                                        &lights,
+                                        max_depth,
+                                        heuristic,
+                                    ),
+                                };
+                                buffer.count(i, row, sample.total());
+                                buffer.splat(x, y, &sample, &filter);
+                                // After the sample, so its random numbers stay the same
+                                if features {
+                                    let hit: FirstHit = first_hit(r, background_, &*world);
+                                    buffer.add_hit(i, row, &hit);
+                                }
+                            }
+                        }
+                    }
+                    if sender.send(buffer).is_err() {
+                        break;
+                    }
+                }
+            });
+            handles.push(handle);
+        }
+        // Only the workers hold senders now, so the loop ends when they do
+        drop(sender);
+
+        let mut buffers: Vec<TileBuffer> = vec![];
+        for buffer in receiver {
+            bar.inc(buffer.samples.iter().map(|&n| n as u64).sum());
+            buffers.push(buffer);
+        }
+        // Margins of neighbouring tiles overlap, so add them up in the same
+        // order whichever thread finished first
+        buffers.sort_by_key(|buffer| (buffer.tile.y0, buffer.tile.x0));
+        for buffer in &buffers {
+            framebuffer.merge(buffer);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        !self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// What `r` hits first. Rays escaping to the background see its color and
+/// no normal.
+pub fn first_hit(r: Ray, background: Color, world: &dyn Hittable) -> FirstHit {
+    let rec = match world.hit(&r, 0.001, f64::INFINITY) {
+        Some(rec) => rec,
+        None => return FirstHit::miss(background),
+    };
+    // Volumes have no surface normal, so they face the camera instead of
+    // passing for background
+    let normal: Vec3 = if rec.normal.length() == 0.0 {
+        -Vec3::unit_vector(r.dir)
+    } else {
+        rec.normal
+    };
+    FirstHit {
+        albedo: rec.mat_ptr.albedo(&rec),
+        normal,
+        t: rec.t,
+        u: rec.u,
+        v: rec.v,
+        object_id: rec.object_id,
+        material_id: rec.mat_ptr.id(),
+    }
+}
+
+pub fn ray_color(
+    r: Ray,
+    background: Color,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    depth: i32,
+) -> Lighting {
+    if depth <= 0 {
+        return Lighting::emitted(Color::new(0.0, 0.0, 0.0));
+    }
+
+    if let Some(hit_rec) = world.hit(&r, 0.001, f64::INFINITY) {
+        let emitted: Color = hit_rec.mat_ptr.emitted(hit_rec.u, hit_rec.v, &hit_rec.p);
+        let lighting = Lighting::emitted(emitted);
+        let srec = match hit_rec.mat_ptr.scatter(&r, &hit_rec) {
+            Some(srec) => srec,
+            None => return lighting,
+        };
+        if let Some(specular_ray) = srec.specular_ray {
+            return lighting.scatter(
+                srec.attenuation,
+                ray_color(specular_ray, background, world, lights, depth - 1),
+            );
+        }
+        let material_pdf = match srec.pdf_ptr {
+            Some(pdf_ptr) => pdf_ptr,
+            None => return lighting,
+        };
+
+        // Half of the diffuse samples go straight towards the lights
+        let light_pdf = HittablePdf::new(lights, hit_rec.p, r.tm);
+        let mixture_pdf = MixturePdf::new(&light_pdf, &*material_pdf);
+        let p: &dyn Pdf = if lights.is_empty() {
+            &*material_pdf
+        } else {
+            &mixture_pdf
+        };
+        let scattered: Ray = Ray::new(hit_rec.p, p.generate(), r.tm);
+        let pdf_val: f64 = p.value(scattered.dir);
+        if pdf_val <= 0.0 {
+            return lighting;
+        }
+
+        lighting.scatter(
+            srec.attenuation * hit_rec.mat_ptr.scattering_pdf(&r, &hit_rec, &scattered) / pdf_val,
+            ray_color(scattered, background, world, lights, depth - 1),
+        )
+    } else {
+        Lighting::emitted(background)
+    }
+}
+
+/// Iterative path tracer with next-event estimation. At every diffuse or
+/// glossy vertex one light sample and one BSDF sample are taken, and each is
+/// weighted against the density of the other strategy so that neither
+/// small lights nor glossy lobes produce fireflies.
+pub fn ray_color_mis(
+    r: Ray,
+    background: Color,
+    world: &dyn Hittable,
+    lights: &HittableList,
+    max_depth: i32,
+    heuristic: MisHeuristic,
+) -> Lighting {
+    let mut radiance = Lighting::emitted(Color::new(0.0, 0.0, 0.0));
+    let mut throughput: Color = Color::new(1.0, 1.0, 1.0);
+    let mut ray: Ray = r;
+    // Density of the BSDF sample that produced `ray`, None after camera and specular rays
+    let mut bsdf_pdf: Option<f64> = None;
+
+    for bounces in 0..max_depth.max(0) as usize {
+        let hit_rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit_rec) => hit_rec,
+            None => {
+                radiance.add(bounces, throughput * background);
+                break;
+            }
+        };
+
+        let emitted: Color = hit_rec.mat_ptr.emitted(hit_rec.u, hit_rec.v, &hit_rec.p);
+        if !emitted.near_zero() {
+            let weight: f64 = match bsdf_pdf {
+                Some(pdf_val) if !lights.is_empty() => {
+                    heuristic.weight(pdf_val, lights.pdf_value(&ray.orig, &ray.dir, ray.tm))
+                }
+                _ => 1.0,
+            };
+            radiance.add(bounces, weight * throughput * emitted);
+        }
+
+        let srec = match hit_rec.mat_ptr.scatter(&ray, &hit_rec) {
+            Some(srec) => srec,
+            None => break,
+        };
+        if let Some(specular_ray) = srec.specular_ray {
+            throughput = throughput * srec.attenuation;
+            ray = specular_ray;
+            bsdf_pdf = None;
+            continue;
+        }
+        let material_pdf = match srec.pdf_ptr {
+            Some(pdf_ptr) => pdf_ptr,
+            None => break,
+        };
+
+        // Light sample, counted only if it reaches a light unoccluded
+        if !lights.is_empty() {
+            let light_ray: Ray = Ray::new(hit_rec.p, lights.random(&hit_rec.p, ray.tm), ray.tm);
+            let light_pdf: f64 = lights.pdf_value(&light_ray.orig, &light_ray.dir, light_ray.tm);
+            if light_pdf > 0.0 {
+                if let (Some(light_rec), Some(world_rec)) = (
+                    lights.hit(&light_ray, 0.001, f64::INFINITY),
+                    world.hit(&light_ray, 0.001, f64::INFINITY),
+                ) {
+                    if (light_rec.t - world_rec.t).abs() <= 1e-6 * light_rec.t.max(1.0) {
+                        let light_emitted: Color =
+                            world_rec
+                                .mat_ptr
+                                .emitted(world_rec.u, world_rec.v, &world_rec.p);
+                        let scattering_pdf: f64 =
+                            hit_rec.mat_ptr.scattering_pdf(&ray, &hit_rec, &light_ray);
+                        let weight: f64 =
+                            heuristic.weight(light_pdf, material_pdf.value(light_ray.dir));
+                        radiance.add(
+                            bounces + 1,
+                            weight * scattering_pdf / light_pdf
+                                * throughput
+                                * srec.attenuation
+                                * light_emitted,
+                        );
+                    }
+                }
+            }
+        }
+
+        // BSDF sample, continues the path
+        let scattered: Ray = Ray::new(hit_rec.p, material_pdf.generate(), ray.tm);
+        let pdf_val: f64 = material_pdf.value(scattered.dir);
+        if pdf_val <= 0.0 {
+            break;
+        }
+        throughput = throughput
+            * srec.attenuation
+            * (hit_rec.mat_ptr.scattering_pdf(&ray, &hit_rec, &scattered) / pdf_val);
+        ray = scattered;
+        bsdf_pdf = Some(pdf_val);
+    }
+
+    radiance
+}
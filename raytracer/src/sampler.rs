@@ -0,0 +1,372 @@
+use std::cell::{Cell, RefCell};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::random::{self, mix};
+
+/// Supplies the sample values of every pixel. A sample is a point in a
+/// space of many dimensions: the position in the pixel, on the lens and in
+/// the shutter interval come first, then two for every bounce. Samplers
+/// only see the numbers of the pixel, the sample and the dimension, so any
+/// thread may take any sample.
+pub trait Sampler {
+    /// Value in [0,1) of `dimension` of sample `index` of `pixel`.
+    fn get_1d(&self, pixel: u64, index: u64, dimension: u32) -> f64;
+    /// Values of `dimension` and the one after it.
+    fn get_2d(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SamplerKind {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler \"{}\"", s)),
+        }
+    }
+}
+
+impl SamplerKind {
+    /// A sampler for `samples_per_pixel` samples, scrambled by `seed`.
+    pub fn build(&self, seed: u64, samples_per_pixel: u64) -> Arc<dyn Sampler + Send + Sync> {
+        match self {
+            SamplerKind::Independent => Arc::new(IndependentSampler { seed }),
+            SamplerKind::Stratified => Arc::new(StratifiedSampler {
+                seed,
+                samples_per_pixel: samples_per_pixel.clamp(1, u32::MAX as u64) as u32,
+            }),
+            SamplerKind::Halton => Arc::new(HaltonSampler { seed }),
+            SamplerKind::Sobol => Arc::new(SobolSampler { seed }),
+        }
+    }
+}
+
+fn hash(seed: u64, pixel: u64, dimension: u32) -> u64 {
+    mix(seed ^ mix(pixel ^ mix(dimension as u64 + 1)))
+}
+
+/// A double in [0,1) from the high bits of a hash.
+fn to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+/// Every value drawn on its own, as the renderer always did.
+pub struct IndependentSampler {
+    pub seed: u64,
+}
+
+impl Sampler for IndependentSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        to_unit(mix(hash(self.seed, pixel, dimension) ^ index))
+    }
+
+    fn get_2d(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dimension),
+            self.get_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+/// Andrew Kensler's hashed permutation of `0..l` from "Correlated
+/// Multi-Jittered Sampling", indexed without building it.
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w: u32 = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            return i.wrapping_add(p) % l;
+        }
+    }
+}
+
+/// Jittered sampling: each dimension, or pair of dimensions on a square
+/// grid, is split into one stratum per sample, and the samples visit the
+/// strata in a different random order for every pixel and dimension.
+pub struct StratifiedSampler {
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        let h: u64 = hash(self.seed, pixel, dimension);
+        let n: u32 = self.samples_per_pixel;
+        let stratum: u32 = permute((index % n as u64) as u32, n, h as u32);
+        let jitter: f64 = to_unit(mix(h ^ index));
+        (stratum as f64 + jitter) / n as f64
+    }
+
+    fn get_2d(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64) {
+        let h: u64 = hash(self.seed, pixel, dimension);
+        // The smallest square grid with a stratum for every sample
+        let n: u32 = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let strata: u32 = n * n;
+        let stratum: u32 = permute((index % strata as u64) as u32, strata, h as u32);
+        let jx: f64 = to_unit(mix(h ^ index));
+        let jy: f64 = to_unit(mix(mix(h ^ index)));
+        (
+            ((stratum % n) as f64 + jx) / n as f64,
+            ((stratum / n) as f64 + jy) / n as f64,
+        )
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// `index` with its digits in `base` mirrored around the radix point.
+fn radical_inverse(base: u64, mut index: u64) -> f64 {
+    let inv_base: f64 = 1.0 / base as f64;
+    let mut inv_base_n: f64 = 1.0;
+    let mut reversed: u64 = 0;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON)
+}
+
+/// The Halton sequence, one prime base per dimension, shifted by a random
+/// offset per pixel and dimension (Cranley-Patterson rotation) so
+/// neighbouring pixels do not repeat each other. Dimensions past the prime
+/// table fall back to independent values.
+pub struct HaltonSampler {
+    pub seed: u64,
+}
+
+impl Sampler for HaltonSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        let h: u64 = hash(self.seed, pixel, dimension);
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => {
+                let v: f64 = radical_inverse(base, index) + to_unit(h);
+                if v >= 1.0 {
+                    v - 1.0
+                } else {
+                    v
+                }
+            }
+            None => to_unit(mix(h ^ index)),
+        }
+    }
+
+    fn get_2d(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(pixel, index, dimension),
+            self.get_1d(pixel, index, dimension + 1),
+        )
+    }
+}
+
+/// The Laine-Karras hash, which like an Owen scramble only lets each bit
+/// depend on the bits below it.
+fn laine_karras(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scrambling of a 32-bit fraction, from Brent Burley's "Practical
+/// Hash-based Owen Scrambling".
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// The first two dimensions of the Sobol sequence.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let x: u32 = index.reverse_bits();
+    let mut y: u32 = 0;
+    let mut v: u32 = 1 << 31;
+    let mut i: u32 = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+/// Owen-scrambled Sobol points. Following Burley, every pair of dimensions
+/// uses the well distributed first two Sobol dimensions, and the samples
+/// are shuffled differently for each pair so the pairs do not correlate.
+/// The shuffle keeps every power-of-two block of samples stratified.
+pub struct SobolSampler {
+    pub seed: u64,
+}
+
+impl SobolSampler {
+    fn point(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64) {
+        let h: u64 = hash(self.seed, pixel, dimension);
+        let shuffled: u32 = owen_scramble(index as u32, h as u32);
+        let (x, y) = sobol_2d(shuffled);
+        let to_f64 = |v: u32| v as f64 / (1u64 << 32) as f64;
+        (
+            to_f64(owen_scramble(x, (h >> 32) as u32)),
+            to_f64(owen_scramble(y, mix(h) as u32)),
+        )
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn get_1d(&self, pixel: u64, index: u64, dimension: u32) -> f64 {
+        self.point(pixel, index, dimension).0
+    }
+
+    fn get_2d(&self, pixel: u64, index: u64, dimension: u32) -> (f64, f64) {
+        self.point(pixel, index, dimension)
+    }
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Arc<dyn Sampler + Send + Sync>>> = RefCell::new(None);
+    /// Pixel, sample number and next free dimension of the current sample.
+    static SAMPLE: Cell<(u64, u64, u32)> = const { Cell::new((0, 0, 0)) };
+}
+
+/// Makes `sampler` supply the values of `get_1d` and `get_2d` on this
+/// thread. Without one they come from the thread's random generator.
+pub fn set_thread_sampler(sampler: Option<Arc<dyn Sampler + Send + Sync>>) {
+    SAMPLER.with(|s| *s.borrow_mut() = sampler);
+}
+
+/// Starts sample `index` of `pixel` from its first dimension.
+pub fn start_sample(pixel: u64, index: u64) {
+    SAMPLE.with(|s| s.set((pixel, index, 0)));
+}
+
+fn next_dimensions(n: u32) -> (u64, u64, u32) {
+    SAMPLE.with(|s| {
+        let (pixel, index, dimension) = s.get();
+        s.set((pixel, index, dimension + n));
+        (pixel, index, dimension)
+    })
+}
+
+/// The next dimension of the current sample.
+pub fn get_1d() -> f64 {
+    SAMPLER.with(|s| match &*s.borrow() {
+        Some(sampler) => {
+            let (pixel, index, dimension) = next_dimensions(1);
+            sampler.get_1d(pixel, index, dimension)
+        }
+        None => random::rng().gen(),
+    })
+}
+
+/// The next two dimensions of the current sample.
+pub fn get_2d() -> (f64, f64) {
+    SAMPLER.with(|s| match &*s.borrow() {
+        Some(sampler) => {
+            let (pixel, index, dimension) = next_dimensions(2);
+            sampler.get_2d(pixel, index, dimension)
+        }
+        None => {
+            let mut rng = random::rng();
+            (rng.gen(), rng.gen())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the points put exactly one sample in each cell of an n by n
+    /// grid.
+    fn fills_grid(points: &[(f64, f64)], n: usize) -> bool {
+        let mut cells = vec![0; n * n];
+        for &(x, y) in points {
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            cells[(y * n as f64) as usize * n + (x * n as f64) as usize] += 1;
+        }
+        cells.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn test_stratified_covers_strata() {
+        let sampler = SamplerKind::Stratified.build(1, 16);
+        let points: Vec<(f64, f64)> = (0..16).map(|i| sampler.get_2d(5, i, 2)).collect();
+        assert!(fills_grid(&points, 4));
+        let mut strata: Vec<usize> = (0..16)
+            .map(|i| (sampler.get_1d(5, i, 4) * 16.0) as usize)
+            .collect();
+        strata.sort();
+        assert_eq!(strata, (0..16).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_sobol_blocks_are_stratified() {
+        let sampler = SamplerKind::Sobol.build(7, 64);
+        for dimension in [0, 2, 10] {
+            let first: Vec<(f64, f64)> = (0..16).map(|i| sampler.get_2d(3, i, dimension)).collect();
+            let second: Vec<(f64, f64)> =
+                (16..32).map(|i| sampler.get_2d(3, i, dimension)).collect();
+            assert!(fills_grid(&first, 4) && fills_grid(&second, 4));
+        }
+        // Pixels and dimension pairs are scrambled differently
+        assert_ne!(sampler.get_2d(3, 0, 0), sampler.get_2d(4, 0, 0));
+        assert_ne!(sampler.get_2d(3, 0, 0), sampler.get_2d(3, 0, 2));
+    }
+
+    #[test]
+    fn test_halton() {
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-12);
+        let sampler = SamplerKind::Halton.build(3, 16);
+        let mut strata: Vec<usize> = (0..16)
+            .map(|i| (sampler.get_1d(9, i, 0) * 16.0) as usize)
+            .collect();
+        strata.sort();
+        strata.dedup();
+        // A rotated run of the base 2 sequence still hits every stratum
+        assert_eq!(strata.len(), 16);
+        assert!("sobol".parse::<SamplerKind>() == Ok(SamplerKind::Sobol));
+        assert!("owen".parse::<SamplerKind>().is_err());
+    }
+}
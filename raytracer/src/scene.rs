@@ -0,0 +1,1324 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::aarect::{XyRect, XzRect, YzRect};
+use crate::animation::{Animation, Keyframes, Lerp};
+use crate::bbox::Box_;
+use crate::bvh::LinearBVH;
+use crate::camera::Camera;
+use crate::constant_medium::ConstantMediun;
+use crate::hittable::{Animated, Hittable, Instance, Transformed};
+use crate::hittable_list::HittableList;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal, Numbered};
+use crate::obj::load_obj;
+use crate::sphere::{MovingSphere, Sphere};
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use crate::transform::{AnimatedTransform, Mat4, Pose, Quat, Transform};
+use crate::triangle::Triangle;
+use crate::vec3::{Color, Vec3};
+
+/// A scene loaded from a description file, ready to be rendered.
+pub struct Scene {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub camera: Camera,
+    pub background: Color,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u64,
+    pub max_depth: i32,
+    /// Timing of the frames when the scene is animated.
+    pub animation: Option<Animation>,
+}
+
+/// An error in a scene file, located by file, line and key.
+#[derive(Debug)]
+pub struct SceneError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if self.line > 0 {
+            write!(f, ":{}:{}", self.line, self.column)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, ": `{}`", key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    animation: Option<AnimationDesc>,
+    #[serde(default)]
+    textures: BTreeMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    /// Objects that are only drawn through instances of them.
+    #[serde(default)]
+    prototypes: BTreeMap<String, ObjectDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    #[serde(default = "default_vfov")]
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    #[serde(default = "default_focus_dist")]
+    focus_dist: f64,
+    #[serde(default)]
+    time0: f64,
+    #[serde(default = "default_time1")]
+    time1: f64,
+    #[serde(default)]
+    keys: Vec<CameraKey>,
+}
+
+/// A keyframe of the camera. Every parameter goes in straight lines
+/// between the keys that set it, and keeps its value from `[camera]` if
+/// none does.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKey {
+    time: f64,
+    lookfrom: Option<[f64; 3]>,
+    lookat: Option<[f64; 3]>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    focus_dist: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDesc {
+    #[serde(default = "default_fps")]
+    fps: f64,
+    /// The first and last frame to render.
+    #[serde(default)]
+    frames: [usize; 2],
+    #[serde(default = "default_shutter_angle")]
+    shutter_angle: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RenderDesc {
+    width: usize,
+    height: usize,
+    samples_per_pixel: u64,
+    max_depth: i32,
+    background: [f64; 3],
+}
+
+impl Default for RenderDesc {
+    fn default() -> Self {
+        Self {
+            width: 400,
+            height: 225,
+            samples_per_pixel: 200,
+            max_depth: 50,
+            background: [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+fn default_vup() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_vfov() -> f64 {
+    40.0
+}
+
+fn default_focus_dist() -> f64 {
+    10.0
+}
+
+fn default_time1() -> f64 {
+    1.0
+}
+
+fn default_fps() -> f64 {
+    Animation::default().fps
+}
+
+fn default_shutter_angle() -> f64 {
+    Animation::default().shutter_angle
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    color: Option<[f64; 3]>,
+    even: Option<[f64; 3]>,
+    odd: Option<[f64; 3]>,
+    scale: Option<f64>,
+    path: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    albedo: Option<[f64; 3]>,
+    texture: Option<Spanned<String>>,
+    fuzz: Option<f64>,
+    ir: Option<f64>,
+    emit: Option<[f64; 3]>,
+    #[serde(default)]
+    keys: Vec<MaterialKey>,
+}
+
+/// A keyframe of the parameters of a material, which change from frame to
+/// frame like those of the camera.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialKey {
+    time: f64,
+    albedo: Option<[f64; 3]>,
+    fuzz: Option<f64>,
+    ir: Option<f64>,
+    emit: Option<[f64; 3]>,
+}
+
+/// A keyframe of an `animated` object, in the keys of `transform`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformKey {
+    time: f64,
+    offset: Option<[f64; 3]>,
+    axis: Option<[f64; 3]>,
+    angle: Option<f64>,
+    scale: Option<[f64; 3]>,
+}
+
+/// The keyframes of a camera, material or object.
+trait Key {
+    fn time(&self) -> f64;
+}
+
+impl Key for CameraKey {
+    fn time(&self) -> f64 {
+        self.time
+    }
+}
+
+impl Key for MaterialKey {
+    fn time(&self) -> f64 {
+        self.time
+    }
+}
+
+impl Key for TransformKey {
+    fn time(&self) -> f64 {
+        self.time
+    }
+}
+
+/// One parameter `get` of `keys` at `time`, going in straight lines
+/// between the keys that set it, or None if none of them does.
+fn track<K: Key, T: Lerp>(keys: &[K], get: impl Fn(&K) -> Option<T>, time: f64) -> Option<T> {
+    let keyed: Vec<(f64, T)> = keys
+        .iter()
+        .filter_map(|key| Some((key.time(), get(key)?)))
+        .collect();
+    Keyframes::new(keyed).map(|keyframes| keyframes.at(time))
+}
+
+/// One entry of `[[objects]]`. Which keys are required depends on `type`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    material: Option<Spanned<String>>,
+    center: Option<[f64; 3]>,
+    center1: Option<[f64; 3]>,
+    time0: Option<f64>,
+    time1: Option<f64>,
+    radius: Option<f64>,
+    x0: Option<f64>,
+    x1: Option<f64>,
+    y0: Option<f64>,
+    y1: Option<f64>,
+    z0: Option<f64>,
+    z1: Option<f64>,
+    k: Option<f64>,
+    p0: Option<[f64; 3]>,
+    p1: Option<[f64; 3]>,
+    p2: Option<[f64; 3]>,
+    density: Option<f64>,
+    color: Option<[f64; 3]>,
+    offset: Option<[f64; 3]>,
+    angle: Option<f64>,
+    axis: Option<[f64; 3]>,
+    scale: Option<[f64; 3]>,
+    offset1: Option<[f64; 3]>,
+    angle1: Option<f64>,
+    axis1: Option<[f64; 3]>,
+    scale1: Option<[f64; 3]>,
+    matrix: Option<[[f64; 4]; 4]>,
+    boundary: Option<Box<ObjectDesc>>,
+    object: Option<Box<ObjectDesc>>,
+    objects: Option<Vec<ObjectDesc>>,
+    path: Option<Spanned<String>>,
+    prototype: Option<Spanned<String>>,
+    #[serde(default)]
+    keys: Vec<TransformKey>,
+}
+
+fn read(path: &Path) -> Result<String, SceneError> {
+    fs::read_to_string(path).map_err(|e| SceneError {
+        file: path.to_path_buf(),
+        line: 0,
+        column: 0,
+        key: None,
+        message: e.to_string(),
+    })
+}
+
+fn vec3(a: [f64; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+/// Returns the 1-based line and column of a byte offset in `src`.
+fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/// Guesses the key a toml error points at from the line it occurs on.
+fn key_at(src: &str, offset: usize) -> Option<String> {
+    let start = src[..offset.min(src.len())]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let line = src[start..].lines().next()?.trim();
+    if line.starts_with('[') {
+        Some(
+            line.trim_matches(|c| c == '[' || c == ']')
+                .trim()
+                .to_string(),
+        )
+    } else {
+        line.split_once('=').map(|(k, _)| k.trim().to_string())
+    }
+}
+
+impl ObjectDesc {
+    /// Whether this is an emitter that can be sampled directly, i.e. a
+    /// sphere, rectangle or triangle with a `diffuse_light` material, or
+    /// one moved by a transform or animation.
+    fn is_light(&self, materials: &BTreeMap<String, MaterialDesc>) -> bool {
+        if matches!(
+            self.kind.get_ref().as_str(),
+            "translate" | "rotate_y" | "transform" | "animated"
+        ) {
+            return self
+                .object
+                .as_ref()
+                .is_some_and(|object| object.is_light(materials));
+        }
+        let sampleable = matches!(
+            self.kind.get_ref().as_str(),
+            "sphere" | "xy_rect" | "xz_rect" | "yz_rect" | "triangle"
+        );
+        let emissive = self
+            .material
+            .as_ref()
+            .and_then(|name| materials.get(name.get_ref()))
+            .is_some_and(|m| m.kind.get_ref() == "diffuse_light");
+        sampleable && emissive
+    }
+}
+
+struct Loader<'a> {
+    file: &'a Path,
+    src: &'a str,
+    textures: BTreeMap<String, Arc<dyn Texture + Send + Sync>>,
+    materials: BTreeMap<String, Arc<dyn Material + Send + Sync>>,
+    prototypes: BTreeMap<String, Arc<dyn Hittable + Send + Sync>>,
+    /// When the camera shutter opens and closes. Keyframed parameters take
+    /// their values at the opening.
+    shutter: (f64, f64),
+}
+
+impl<'a> Loader<'a> {
+    fn error(&self, span: Range<usize>, key: String, message: String) -> SceneError {
+        let (line, column) = line_col(self.src, span.start);
+        SceneError {
+            file: self.file.to_path_buf(),
+            line,
+            column,
+            key: Some(key),
+            message,
+        }
+    }
+
+    fn require<T>(
+        &self,
+        value: Option<T>,
+        kind: &Spanned<String>,
+        path: &str,
+        key: &str,
+    ) -> Result<T, SceneError> {
+        value.ok_or_else(|| {
+            self.error(
+                kind.span(),
+                format!("{}.{}", path, key),
+                format!("`{}` requires key `{}`", kind.get_ref(), key),
+            )
+        })
+    }
+
+    fn animation(&self, desc: &AnimationDesc) -> Result<Animation, SceneError> {
+        // The values have no spans to point at
+        let check = |ok: bool, key: &str, message: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(SceneError {
+                    file: self.file.to_path_buf(),
+                    line: 0,
+                    column: 0,
+                    key: Some(format!("animation.{}", key)),
+                    message: message.to_string(),
+                })
+            }
+        };
+        check(desc.fps > 0.0, "fps", "must be positive")?;
+        check(
+            desc.frames[0] <= desc.frames[1],
+            "frames",
+            "the last frame comes before the first",
+        )?;
+        check(
+            (0.0..=360.0).contains(&desc.shutter_angle),
+            "shutter_angle",
+            "must be from 0 to 360 degrees",
+        )?;
+        Ok(Animation {
+            fps: desc.fps,
+            first_frame: desc.frames[0],
+            last_frame: desc.frames[1],
+            shutter_angle: desc.shutter_angle,
+        })
+    }
+
+    fn texture(
+        &self,
+        name: &str,
+        desc: &TextureDesc,
+    ) -> Result<Arc<dyn Texture + Send + Sync>, SceneError> {
+        let path = format!("textures.{}", name);
+        let kind = &desc.kind;
+        let texture: Arc<dyn Texture + Send + Sync> = match kind.get_ref().as_str() {
+            "solid" => Arc::new(SolidColor::new(vec3(
+                self.require(desc.color, kind, &path, "color")?,
+            ))),
+            "checker" => Arc::new(CheckerTexture::new_from_color(
+                vec3(self.require(desc.even, kind, &path, "even")?),
+                vec3(self.require(desc.odd, kind, &path, "odd")?),
+            )),
+            "noise" => Arc::new(NoiseTexture::new_sc(
+                self.require(desc.scale, kind, &path, "scale")?,
+            )),
+            "image" => {
+                let image = self.require(desc.path.as_ref(), kind, &path, "path")?;
+                let dir = self.file.parent().unwrap_or_else(|| Path::new(""));
+                let filename = dir.join(image.get_ref());
+                match ImageTexture::open(&filename.to_string_lossy()) {
+                    Ok(texture) => Arc::new(texture),
+                    Err(e) => {
+                        return Err(self.error(
+                            image.span(),
+                            format!("{}.path", path),
+                            format!("cannot open image \"{}\": {}", filename.display(), e),
+                        ))
+                    }
+                }
+            }
+            other => {
+                return Err(self.error(
+                    kind.span(),
+                    format!("{}.type", path),
+                    format!("unknown texture type \"{}\"", other),
+                ))
+            }
+        };
+        Ok(texture)
+    }
+
+    fn lookup_texture(
+        &self,
+        name: &Spanned<String>,
+        key: String,
+    ) -> Result<Arc<dyn Texture + Send + Sync>, SceneError> {
+        self.textures.get(name.get_ref()).cloned().ok_or_else(|| {
+            self.error(
+                name.span(),
+                key,
+                format!("unknown texture \"{}\"", name.get_ref()),
+            )
+        })
+    }
+
+    fn material(
+        &self,
+        name: &str,
+        desc: &MaterialDesc,
+    ) -> Result<Arc<dyn Material + Send + Sync>, SceneError> {
+        let path = format!("materials.{}", name);
+        let kind = &desc.kind;
+        let time: f64 = self.shutter.0;
+        let albedo: Option<Vec3> =
+            track(&desc.keys, |k| k.albedo.map(vec3), time).or(desc.albedo.map(vec3));
+        let fuzz: Option<f64> = track(&desc.keys, |k| k.fuzz, time).or(desc.fuzz);
+        let ir: Option<f64> = track(&desc.keys, |k| k.ir, time).or(desc.ir);
+        let emit: Option<Color> =
+            track(&desc.keys, |k| k.emit.map(vec3), time).or(desc.emit.map(vec3));
+        let material: Arc<dyn Material + Send + Sync> = match kind.get_ref().as_str() {
+            "lambertian" => match &desc.texture {
+                Some(texture) => Arc::new(Lambertian::new_arc(
+                    self.lookup_texture(texture, format!("{}.texture", path))?,
+                )),
+                None => Arc::new(Lambertian::new(
+                    self.require(albedo, kind, &path, "albedo")?,
+                )),
+            },
+            "metal" => Arc::new(Metal::new(
+                self.require(albedo, kind, &path, "albedo")?,
+                fuzz.unwrap_or(0.0),
+            )),
+            "dielectric" => Arc::new(Dielectric::new(self.require(ir, kind, &path, "ir")?)),
+            "diffuse_light" => match &desc.texture {
+                Some(texture) => Arc::new(DiffuseLight::new(
+                    self.lookup_texture(texture, format!("{}.texture", path))?,
+                )),
+                None => Arc::new(DiffuseLight::new_col(
+                    self.require(emit, kind, &path, "emit")?,
+                )),
+            },
+            other => {
+                return Err(self.error(
+                    kind.span(),
+                    format!("{}.type", path),
+                    format!("unknown material type \"{}\"", other),
+                ))
+            }
+        };
+        Ok(material)
+    }
+
+    fn lookup_material(
+        &self,
+        obj: &ObjectDesc,
+        path: &str,
+    ) -> Result<Arc<dyn Material + Send + Sync>, SceneError> {
+        let name = self.require(obj.material.as_ref(), &obj.kind, path, "material")?;
+        self.materials.get(name.get_ref()).cloned().ok_or_else(|| {
+            self.error(
+                name.span(),
+                format!("{}.material", path),
+                format!("unknown material \"{}\"", name.get_ref()),
+            )
+        })
+    }
+
+    /// The transform a `translate`, `rotate_y` or `transform` object applies
+    /// to its `object`, None for other types.
+    fn transform(&self, obj: &ObjectDesc, path: &str) -> Result<Option<Transform>, SceneError> {
+        let kind = &obj.kind;
+        let transform: Transform = match kind.get_ref().as_str() {
+            "translate" => {
+                Transform::translate(vec3(self.require(obj.offset, kind, path, "offset")?))
+            }
+            "rotate_y" => Transform::rotate_y(self.require(obj.angle, kind, path, "angle")?),
+            "transform" => self.affine(obj, path)?,
+            _ => return Ok(None),
+        };
+        Ok(Some(transform))
+    }
+
+    /// The transform of a `transform` or `instance` object: it scales by
+    /// `scale`, rotates by `angle` degrees about `axis`, then moves by
+    /// `offset`, after applying `matrix` if there is one.
+    fn affine(&self, obj: &ObjectDesc, path: &str) -> Result<Transform, SceneError> {
+        let kind = &obj.kind;
+        let matrix: Transform = match obj.matrix {
+            Some(m) if m[3] == [0.0, 0.0, 0.0, 1.0] => {
+                Transform::new(Mat4::new(m)).ok_or_else(|| {
+                    self.error(
+                        kind.span(),
+                        format!("{}.matrix", path),
+                        "the matrix cannot be inverted".to_string(),
+                    )
+                })?
+            }
+            Some(_) => {
+                return Err(self.error(
+                    kind.span(),
+                    format!("{}.matrix", path),
+                    "the last row of the matrix must be [0, 0, 0, 1]".to_string(),
+                ))
+            }
+            None => Transform::identity(),
+        };
+        let scale: [f64; 3] = obj.scale.unwrap_or([1.0, 1.0, 1.0]);
+        if scale.contains(&0.0) {
+            return Err(self.error(
+                kind.span(),
+                format!("{}.scale", path),
+                "scale factors cannot be 0".to_string(),
+            ));
+        }
+        Ok(
+            Transform::translate(vec3(obj.offset.unwrap_or([0.0, 0.0, 0.0])))
+                * Transform::rotate(
+                    vec3(obj.axis.unwrap_or([0.0, 1.0, 0.0])),
+                    obj.angle.unwrap_or(0.0),
+                )
+                * Transform::scale(vec3(scale))
+                * matrix,
+        )
+    }
+
+    /// The pose an `animated` object starts from with the keys `offset`,
+    /// `axis`, `angle` and `scale`, or ends at with the same keys ending in
+    /// 1. Those left out at the end stay as they were at the start.
+    fn pose(&self, obj: &ObjectDesc, path: &str, start: Option<&Pose>) -> Result<Pose, SceneError> {
+        let (offset, axis, angle, scale) = match start {
+            None => (obj.offset, obj.axis, obj.angle, obj.scale),
+            Some(_) => (obj.offset1, obj.axis1.or(obj.axis), obj.angle1, obj.scale1),
+        };
+        let key = if start.is_some() { "scale1" } else { "scale" };
+        if scale.is_some_and(|s| s.contains(&0.0)) {
+            return Err(self.error(
+                obj.kind.span(),
+                format!("{}.{}", path, key),
+                "scale factors cannot be 0".to_string(),
+            ));
+        }
+        let default: Pose = start.copied().unwrap_or_default();
+        Ok(Pose {
+            translation: offset.map_or(default.translation, vec3),
+            rotation: match angle {
+                Some(angle) => Quat::from_axis_angle(vec3(axis.unwrap_or([0.0, 1.0, 0.0])), angle),
+                None => default.rotation,
+            },
+            scale: scale.map_or(default.scale, vec3),
+        })
+    }
+
+    /// The motion of an `animated` object with `keys`. Its offset, scale
+    /// and rotation are keyed apart, each starting from the object's own.
+    fn keyframed(&self, obj: &ObjectDesc, path: &str) -> Result<AnimatedTransform, SceneError> {
+        for (i, key) in obj.keys.iter().enumerate() {
+            if key.scale.is_some_and(|s| s.contains(&0.0)) {
+                return Err(self.error(
+                    obj.kind.span(),
+                    format!("{}.keys[{}].scale", path, i),
+                    "scale factors cannot be 0".to_string(),
+                ));
+            }
+        }
+        let base: Pose = self.pose(obj, path, None)?;
+        let rotation = |key: &TransformKey| {
+            let axis: [f64; 3] = key.axis.or(obj.axis).unwrap_or([0.0, 1.0, 0.0]);
+            Some(Quat::from_axis_angle(vec3(axis), key.angle?))
+        };
+        // Poses at the times of all keys, between which every part is
+        // blended the same way as on its own
+        let poses: Vec<(f64, Pose)> = obj
+            .keys
+            .iter()
+            .map(|key| {
+                let time: f64 = key.time;
+                let pose = Pose {
+                    translation: track(&obj.keys, |k| k.offset.map(vec3), time)
+                        .unwrap_or(base.translation),
+                    rotation: track(&obj.keys, rotation, time).unwrap_or(base.rotation),
+                    scale: track(&obj.keys, |k| k.scale.map(vec3), time).unwrap_or(base.scale),
+                };
+                (time, pose)
+            })
+            .collect();
+        Ok(AnimatedTransform::from_keys(Keyframes::new(poses).unwrap()))
+    }
+
+    fn object(
+        &self,
+        obj: &ObjectDesc,
+        path: &str,
+    ) -> Result<Arc<dyn Hittable + Send + Sync>, SceneError> {
+        let kind = &obj.kind;
+        let object: Arc<dyn Hittable + Send + Sync> = match kind.get_ref().as_str() {
+            "sphere" => Arc::new(Sphere::new(
+                vec3(self.require(obj.center, kind, path, "center")?),
+                self.require(obj.radius, kind, path, "radius")?,
+                self.lookup_material(obj, path)?,
+            )),
+            "moving_sphere" => Arc::new(MovingSphere::new(
+                vec3(self.require(obj.center, kind, path, "center")?),
+                vec3(self.require(obj.center1, kind, path, "center1")?),
+                obj.time0.unwrap_or(0.0),
+                obj.time1.unwrap_or(1.0),
+                self.require(obj.radius, kind, path, "radius")?,
+                self.lookup_material(obj, path)?,
+            )),
+            "xy_rect" => Arc::new(XyRect::new(
+                self.require(obj.x0, kind, path, "x0")?,
+                self.require(obj.x1, kind, path, "x1")?,
+                self.require(obj.y0, kind, path, "y0")?,
+                self.require(obj.y1, kind, path, "y1")?,
+                self.require(obj.k, kind, path, "k")?,
+                self.lookup_material(obj, path)?,
+            )),
+            "xz_rect" => Arc::new(XzRect::new(
+                self.require(obj.x0, kind, path, "x0")?,
+                self.require(obj.x1, kind, path, "x1")?,
+                self.require(obj.z0, kind, path, "z0")?,
+                self.require(obj.z1, kind, path, "z1")?,
+                self.require(obj.k, kind, path, "k")?,
+                self.lookup_material(obj, path)?,
+            )),
+            "yz_rect" => Arc::new(YzRect::new(
+                self.require(obj.y0, kind, path, "y0")?,
+                self.require(obj.y1, kind, path, "y1")?,
+                self.require(obj.z0, kind, path, "z0")?,
+                self.require(obj.z1, kind, path, "z1")?,
+                self.require(obj.k, kind, path, "k")?,
+                self.lookup_material(obj, path)?,
+            )),
+            "box" => Arc::new(Box_::new(
+                vec3(self.require(obj.p0, kind, path, "p0")?),
+                vec3(self.require(obj.p1, kind, path, "p1")?),
+                self.lookup_material(obj, path)?,
+            )),
+            "triangle" => Arc::new(Triangle::new(
+                vec3(self.require(obj.p0, kind, path, "p0")?),
+                vec3(self.require(obj.p1, kind, path, "p1")?),
+                vec3(self.require(obj.p2, kind, path, "p2")?),
+                self.lookup_material(obj, path)?,
+            )),
+            "mesh" => {
+                let file = self.require(obj.path.as_ref(), kind, path, "path")?;
+                let material = match obj.material {
+                    Some(_) => Some(self.lookup_material(obj, path)?),
+                    None => None,
+                };
+                let dir = self.file.parent().unwrap_or_else(|| Path::new(""));
+                let meshes = load_obj(&dir.join(file.get_ref()), material).map_err(|e| {
+                    self.error(file.span(), format!("{}.path", path), e.to_string())
+                })?;
+                let mut list = HittableList::new();
+                for mesh in meshes {
+                    list.add(Arc::new(mesh));
+                }
+                if list.is_empty() {
+                    return Err(self.error(
+                        file.span(),
+                        format!("{}.path", path),
+                        format!("\"{}\" has no faces", file.get_ref()),
+                    ));
+                }
+                Arc::new(LinearBVH::new(list, 0.0, 1.0))
+            }
+            "constant_medium" => {
+                let boundary = self.require(obj.boundary.as_ref(), kind, path, "boundary")?;
+                Arc::new(ConstantMediun::new_col(
+                    self.object(boundary, &format!("{}.boundary", path))?,
+                    self.require(obj.density, kind, path, "density")?,
+                    vec3(self.require(obj.color, kind, path, "color")?),
+                ))
+            }
+            "translate" | "rotate_y" | "transform" => {
+                // Transforms nested in each other fold into one matrix
+                let mut transform = Transform::identity();
+                let mut node: &ObjectDesc = obj;
+                let mut node_path: String = path.to_string();
+                while let Some(t) = self.transform(node, &node_path)? {
+                    transform = transform * t;
+                    node = self.require(node.object.as_ref(), &node.kind, &node_path, "object")?;
+                    node_path = format!("{}.object", node_path);
+                }
+                Arc::new(Transformed::new(self.object(node, &node_path)?, transform))
+            }
+            "instance" => {
+                let name = self.require(obj.prototype.as_ref(), kind, path, "prototype")?;
+                let prototype = self
+                    .prototypes
+                    .get(name.get_ref())
+                    .cloned()
+                    .ok_or_else(|| {
+                        self.error(
+                            name.span(),
+                            format!("{}.prototype", path),
+                            format!("unknown prototype \"{}\"", name.get_ref()),
+                        )
+                    })?;
+                let transform: Transform = self.affine(obj, path)?;
+                match obj.material {
+                    Some(_) => Arc::new(Instance::with_material(
+                        prototype,
+                        transform,
+                        self.lookup_material(obj, path)?,
+                    )),
+                    None => Arc::new(Instance::new(prototype, transform)),
+                }
+            }
+            "animated" => {
+                let motion: AnimatedTransform = if obj.keys.is_empty() {
+                    let start: Pose = self.pose(obj, path, None)?;
+                    let end: Pose = self.pose(obj, path, Some(&start))?;
+                    AnimatedTransform::new(
+                        start,
+                        end,
+                        obj.time0.unwrap_or(0.0),
+                        obj.time1.unwrap_or(1.0),
+                    )
+                } else {
+                    self.keyframed(obj, path)?
+                };
+                let object = self.require(obj.object.as_ref(), kind, path, "object")?;
+                Arc::new(Animated::new(
+                    self.object(object, &format!("{}.object", path))?,
+                    motion,
+                ))
+            }
+            "bvh" => {
+                let objects = self.require(obj.objects.as_ref(), kind, path, "objects")?;
+                let mut list = HittableList::new();
+                for (i, inner) in objects.iter().enumerate() {
+                    list.add(self.object(inner, &format!("{}.objects[{}]", path, i))?);
+                }
+                if list.hittable_list.is_empty() {
+                    return Err(self.error(
+                        kind.span(),
+                        format!("{}.objects", path),
+                        "`bvh` needs at least one object".to_string(),
+                    ));
+                }
+                Arc::new(LinearBVH::new(
+                    list,
+                    obj.time0.unwrap_or(self.shutter.0),
+                    obj.time1.unwrap_or(self.shutter.1),
+                ))
+            }
+            other => {
+                return Err(self.error(
+                    kind.span(),
+                    format!("{}.type", path),
+                    format!("unknown object type \"{}\"", other),
+                ))
+            }
+        };
+        Ok(object)
+    }
+}
+
+impl Scene {
+    /// Reads and builds the scene described by the TOML file at `path`.
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        Scene::from_str(&read(path)?, path)
+    }
+
+    /// Reads the scene at `path` as it is with the shutter open from
+    /// `time0` to `time1`, e.g. for a frame of its animation.
+    pub fn load_at(path: &Path, time0: f64, time1: f64) -> Result<Scene, SceneError> {
+        Scene::from_str_at(&read(path)?, path, time0, time1)
+    }
+
+    /// Builds a scene from TOML source; `path` is used for error messages
+    /// and to resolve relative image paths. The shutter opens and closes
+    /// at the `time0` and `time1` of its camera.
+    pub fn from_str(src: &str, path: &Path) -> Result<Scene, SceneError> {
+        Scene::build(src, path, None)
+    }
+
+    pub fn from_str_at(
+        src: &str,
+        path: &Path,
+        time0: f64,
+        time1: f64,
+    ) -> Result<Scene, SceneError> {
+        Scene::build(src, path, Some((time0, time1)))
+    }
+
+    fn build(src: &str, path: &Path, shutter: Option<(f64, f64)>) -> Result<Scene, SceneError> {
+        let desc: SceneFile = toml::from_str(src).map_err(|e| {
+            let offset = e.span().map_or(0, |span| span.start);
+            let (line, column) = line_col(src, offset);
+            SceneError {
+                file: path.to_path_buf(),
+                line,
+                column,
+                key: key_at(src, offset),
+                message: e.message().to_string(),
+            }
+        })?;
+
+        let cam = &desc.camera;
+        let mut loader = Loader {
+            file: path,
+            src,
+            textures: BTreeMap::new(),
+            materials: BTreeMap::new(),
+            prototypes: BTreeMap::new(),
+            shutter: shutter.unwrap_or((cam.time0, cam.time1)),
+        };
+        let animation: Option<Animation> = match &desc.animation {
+            Some(a) => Some(loader.animation(a)?),
+            None => None,
+        };
+        for (name, texture) in &desc.textures {
+            let texture = loader.texture(name, texture)?;
+            loader.textures.insert(name.clone(), texture);
+        }
+        // Materials are numbered in name order for the material ID pass, so
+        // every frame and crop of a scene agrees on them
+        for (i, (name, material)) in desc.materials.iter().enumerate() {
+            let material = loader.material(name, material)?;
+            let material = Arc::new(Numbered::new(material, i as u32 + 1));
+            loader.materials.insert(name.clone(), material);
+        }
+        // Prototypes may instance the ones named before them
+        for (name, prototype) in &desc.prototypes {
+            let prototype = loader.object(prototype, &format!("prototypes.{}", name))?;
+            loader.prototypes.insert(name.clone(), prototype);
+        }
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        for (i, obj) in desc.objects.iter().enumerate() {
+            let object = loader.object(obj, &format!("objects[{}]", i))?;
+            if obj.is_light(&desc.materials) {
+                lights.add(object.clone());
+            }
+            world.add(object);
+        }
+
+        let render = &desc.render;
+        // The camera stays where it is when the shutter opens
+        let (time0, time1) = loader.shutter;
+        let keys: &[CameraKey] = &cam.keys;
+        let camera = Camera::new(
+            track(keys, |k| k.lookfrom.map(vec3), time0).unwrap_or(vec3(cam.lookfrom)),
+            track(keys, |k| k.lookat.map(vec3), time0).unwrap_or(vec3(cam.lookat)),
+            vec3(cam.vup),
+            track(keys, |k| k.vfov, time0).unwrap_or(cam.vfov),
+            render.width as f64 / render.height as f64,
+            track(keys, |k| k.aperture, time0).unwrap_or(cam.aperture),
+            track(keys, |k| k.focus_dist, time0).unwrap_or(cam.focus_dist),
+            time0,
+            time1,
+        );
+
+        Ok(Scene {
+            world,
+            lights,
+            camera,
+            background: vec3(render.background),
+            width: render.width,
+            height: render.height,
+            samples_per_pixel: render.samples_per_pixel,
+            max_depth: render.max_depth,
+            animation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::vec3::Point3;
+
+    const MINIMAL: &str = r#"
+[camera]
+lookfrom = [0, 0, -5]
+lookat = [0, 0, 0]
+
+[materials.white]
+type = "lambertian"
+albedo = [0.73, 0.73, 0.73]
+"#;
+
+    fn load(objects: &str) -> Result<Scene, SceneError> {
+        Scene::from_str(&format!("{}{}", MINIMAL, objects), Path::new("test.toml"))
+    }
+
+    #[test]
+    fn test_load_objects() {
+        let scene = load(
+            r#"
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+
+[[objects]]
+type = "translate"
+offset = [1, 2, 3]
+[objects.object]
+type = "box"
+p0 = [0, 0, 0]
+p1 = [1, 1, 1]
+material = "white"
+
+[[objects]]
+type = "translate"
+offset = [10, 0, 0]
+[objects.object]
+type = "transform"
+scale = [2, 1, 1]
+axis = [0, 0, 1]
+angle = 90
+[objects.object.object]
+type = "box"
+p0 = [0, 0, 0]
+p1 = [1, 1, 1]
+material = "white"
+"#,
+        )
+        .unwrap();
+        assert_eq!(scene.world.hittable_list.len(), 3);
+        // Scaled along x, turned upright, then moved
+        let box_ = scene.world.hittable_list[2].bounding_box(0.0, 1.0).unwrap();
+        assert!((box_.min() - Vec3::new(9.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((box_.max() - Vec3::new(10.0, 2.0, 1.0)).length() < 1e-9);
+        assert!(scene.lights.is_empty());
+        assert_eq!((scene.width, scene.height), (400, 225));
+    }
+
+    #[test]
+    fn test_transformed_lights() {
+        let scene = load(
+            r#"
+[materials.light]
+type = "diffuse_light"
+emit = [4, 4, 4]
+
+[[objects]]
+type = "translate"
+offset = [0, 1, 0]
+[objects.object]
+type = "transform"
+scale = [2, 1, 1]
+[objects.object.object]
+type = "xz_rect"
+x0 = -1
+x1 = 1
+z0 = -1
+z1 = 1
+k = 3
+material = "light"
+
+[[objects]]
+type = "translate"
+offset = [0, 1, 0]
+[objects.object]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+"#,
+        )
+        .unwrap();
+        assert_eq!(scene.lights.hittable_list.len(), 1);
+        // Stretched to 4 by 2 at y = 4
+        let o = Point3::new(0.0, 0.0, 0.0);
+        let pdf: f64 = scene.lights.pdf_value(&o, &Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert!((pdf - 16.0 / 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_instances() {
+        let scene = load(
+            r#"
+[materials.red]
+type = "lambertian"
+albedo = [0.9, 0.1, 0.1]
+
+[prototypes.ball]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+
+[[objects]]
+type = "instance"
+prototype = "ball"
+offset = [5, 0, 0]
+scale = [2, 2, 2]
+
+[[objects]]
+type = "instance"
+prototype = "ball"
+material = "red"
+"#,
+        )
+        .unwrap();
+        assert_eq!(scene.world.hittable_list.len(), 2);
+        let box_ = scene.world.hittable_list[0].bounding_box(0.0, 1.0).unwrap();
+        assert!((box_.min() - Vec3::new(3.0, -2.0, -2.0)).length() < 1e-9);
+        assert!((box_.max() - Vec3::new(7.0, 2.0, 2.0)).length() < 1e-9);
+        // Materials are numbered by name, red before white
+        let material_id = |object: usize, x: f64| {
+            let r = Ray::new(Point3::new(x, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+            let rec = scene.world.hittable_list[object].hit(&r, 0.001, f64::INFINITY);
+            rec.unwrap().mat_ptr.id()
+        };
+        assert_eq!(material_id(0, 5.0), 2);
+        assert_eq!(material_id(1, 0.0), 1);
+
+        let err = load(
+            r#"
+[[objects]]
+type = "instance"
+prototype = "cube"
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.key.as_deref(), Some("objects[0].prototype"));
+    }
+
+    #[test]
+    fn test_animated() {
+        let scene = load(
+            r#"
+[[objects]]
+type = "animated"
+offset = [0, 0, 0]
+offset1 = [3, 0, 0]
+angle1 = 90
+scale1 = [2, 2, 2]
+[objects.object]
+type = "box"
+p0 = [-1, -1, -1]
+p1 = [1, 1, 1]
+material = "white"
+"#,
+        )
+        .unwrap();
+        let box_ = scene.world.hittable_list[0].bounding_box(0.0, 1.0).unwrap();
+        assert!(box_.min().x() <= -1.0 && box_.max().x() >= 5.0);
+        assert!(box_.max().y() >= 2.0);
+
+        // Light samples see a moving light where it is at their time
+        let src = format!(
+            "{}{}",
+            MINIMAL,
+            r#"
+[materials.light]
+type = "diffuse_light"
+emit = [4, 4, 4]
+
+[[objects]]
+type = "animated"
+offset = [0, 0, 0]
+offset1 = [4, 0, 0]
+[objects.object]
+type = "sphere"
+center = [0, 0, 0]
+radius = 0.5
+material = "light"
+"#
+        );
+        let scene = Scene::from_str(&src, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.lights.hittable_list.len(), 1);
+        let above = |x: f64, time: f64| {
+            let o = Point3::new(x, 5.0, 0.0);
+            scene.lights.pdf_value(&o, &Vec3::new(0.0, -1.0, 0.0), time)
+        };
+        assert!(above(3.0, 0.75) > 0.0);
+        assert_eq!(above(0.0, 0.75), 0.0);
+        assert!(above(0.0, 0.0) > 0.0);
+        let o = Point3::new(0.0, 5.0, 0.0);
+        for time in [0.0, 0.25, 0.75] {
+            let r = Ray::new(o, scene.lights.random(&o, time), time);
+            assert!(scene.lights.hit(&r, 0.001, f64::INFINITY).is_some());
+        }
+
+        let err = load(
+            r#"
+[[objects]]
+type = "animated"
+scale1 = [1, 0, 1]
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.key.as_deref(), Some("objects[0].scale1"));
+    }
+
+    #[test]
+    fn test_keyframes() {
+        let src = format!(
+            "{}{}",
+            MINIMAL,
+            r#"
+[animation]
+fps = 10
+frames = [0, 20]
+shutter_angle = 90
+
+[[camera.keys]]
+time = 0
+lookfrom = [0, 0, -5]
+[[camera.keys]]
+time = 2
+lookfrom = [0, 0, -9]
+
+[materials.glow]
+type = "diffuse_light"
+emit = [1, 1, 1]
+[[materials.glow.keys]]
+time = 1
+emit = [4, 4, 4]
+
+[[objects]]
+type = "animated"
+[[objects.keys]]
+time = 0
+offset = [0, 0, 0]
+[[objects.keys]]
+time = 1
+offset = [2, 0, 0]
+[objects.object]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "glow"
+"#
+        );
+        let path = Path::new("test.toml");
+        let animation = Scene::from_str(&src, path).unwrap().animation.unwrap();
+        assert_eq!(animation.frames(), 0..=20);
+        assert_eq!(animation.shutter_angle, 90.0);
+
+        let (time0, time1) = animation.shutter(5);
+        let scene = Scene::from_str_at(&src, path, time0, time1).unwrap();
+        assert_eq!(scene.camera.shutter(), (0.5, 0.525));
+        // A quarter of the way from the first camera key to the second
+        let r = scene.camera.get_ray(0.5, 0.5);
+        assert!((r.orig - Point3::new(0.0, 0.0, -6.0)).length() < 1e-9);
+        // The sphere is moving right while the shutter is open
+        let box_ = scene.world.hittable_list[0]
+            .bounding_box(time0, time1)
+            .unwrap();
+        assert!((box_.min().x() - 0.0).abs() < 1e-9);
+        assert!((box_.max().x() - 2.05).abs() < 1e-9);
+        let rec = scene.world.hittable_list[0]
+            .hit(
+                &Ray::new(r.orig, Vec3::new(0.0, 0.0, 1.0), time0),
+                0.001,
+                f64::INFINITY,
+            )
+            .unwrap();
+        assert_eq!(
+            rec.mat_ptr.emitted(0.0, 0.0, &rec.p),
+            Color::new(4.0, 4.0, 4.0)
+        );
+
+        let err = Scene::from_str(&src.replace("fps = 10", "fps = 0"), path)
+            .err()
+            .unwrap();
+        assert_eq!(err.key.as_deref(), Some("animation.fps"));
+    }
+
+    #[test]
+    fn test_bad_transforms() {
+        let err = load(
+            r#"
+[[objects]]
+type = "transform"
+matrix = [[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 1]]
+[objects.object]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.key.as_deref(), Some("objects[0].matrix"));
+        let err = load(
+            r#"
+[[objects]]
+type = "rotate_y"
+angle = 30
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.key.as_deref(), Some("objects[0].object"));
+    }
+
+    #[test]
+    fn test_unknown_key_reports_line() {
+        let err = load(
+            r#"
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radus = 1
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.line, 13);
+        assert_eq!(err.key.as_deref(), Some("radus"));
+    }
+
+    #[test]
+    fn test_unknown_material_reports_key() {
+        let err = load(
+            r#"
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "red"
+"#,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.line, 14);
+        assert_eq!(err.key.as_deref(), Some("objects[0].material"));
+    }
+}
@@ -2,6 +2,7 @@ use crate::aabb::Aabb;
 use crate::hittable::HitRecord;
 use crate::hittable::Hittable;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
@@ -32,7 +33,7 @@ impl<M: Material> Sphere<M> {
 }
 
 impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc: Vec3 = r.orig - self.center;
         let a: f64 = r.dir.squared_length();
         let half_b: f64 = Vec3::dot(oc, r.dir);
@@ -68,6 +69,35 @@ impl<M: Material> Hittable for Sphere<M> {
         );
         Some(output_box)
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if self
+            .hit(&Ray::new(*o, *v, time), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
+        let distance_squared: f64 = (self.center - *o).squared_length();
+        // From inside, the sphere is all around and is sampled uniformly
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * std::f64::consts::PI);
+        }
+        let cos_theta_max: f64 = (1.0 - self.radius * self.radius / distance_squared)
+            .max(0.0)
+            .sqrt();
+        let solid_angle: f64 = 2.0 * std::f64::consts::PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, o: &Point3, _time: f64) -> Vec3 {
+        let direction: Vec3 = self.center - *o;
+        let distance_squared: f64 = direction.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::random_unit_vector();
+        }
+        let uvw: Onb = Onb::build_from_w(direction);
+        uvw.local(Vec3::random_to_sphere(self.radius, distance_squared))
+    }
 }
 
 pub struct MovingSphere<M: Material> {
@@ -98,7 +128,7 @@ impl<M: Material> MovingSphere<M> {
 }
 
 impl<M: Material> Hittable for MovingSphere<M> {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc: Vec3 = r.orig - MovingSphere::center(self, r.tm);
         let a: f64 = r.dir.squared_length();
         let half_b: f64 = Vec3::dot(oc, r.dir);
@@ -140,3 +170,34 @@ impl<M: Material> Hittable for MovingSphere<M> {
         Some(output_box)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::Color;
+
+    #[test]
+    fn test_pdf_value() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+        );
+        let outside = Point3::new(0.0, 0.0, -2.0);
+        let toward: Vec3 = sphere.random(&outside, 0.0);
+        assert!(Vec3::dot(toward, Vec3::new(0.0, 0.0, 1.0)) > 0.0);
+        // A cone of half angle 30 degrees
+        let cone: f64 = 2.0 * std::f64::consts::PI * (1.0 - 0.75_f64.sqrt());
+        let pdf: f64 = sphere.pdf_value(&outside, &Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!((pdf - 1.0 / cone).abs() < 1e-9);
+        // Every direction from inside sees the sphere
+        let inside = Point3::new(0.0, 0.5, 0.0);
+        for _ in 0..16 {
+            let v: Vec3 = sphere.random(&inside, 0.0);
+            assert!((v.length() - 1.0).abs() < 1e-9);
+            let pdf: f64 = sphere.pdf_value(&inside, &v, 0.0);
+            assert!((pdf - 1.0 / (4.0 * std::f64::consts::PI)).abs() < 1e-12);
+        }
+    }
+}
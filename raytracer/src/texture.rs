@@ -1,5 +1,4 @@
 use image::ImageBuffer;
-pub use std::path::*;
 use std::sync::Arc;
 
 use crate::{
@@ -99,28 +98,26 @@ pub struct ImageTexture {
     pub data: ImageBuffer<image::Rgb<u8>, std::vec::Vec<u8>>,
     pub width: usize,
     pub height: usize,
-    pub bytes_per_scanline: usize,
 }
 
 impl ImageTexture {
     //图片读取基于https://github.com/Junxix/raytracer-2021/blob/master/raytracer/src/texture.rs
-    const BYTES_PER_PIXEL: usize = 3;
 
     pub fn new(filename: &str) -> Self {
-        // let components_per_pixel = ImageTexture::BYTES_PER_PIXEL;
+        ImageTexture::open(filename).unwrap()
+    }
 
-        let data = image::open(filename).unwrap().to_rgb8();
+    pub fn open(filename: &str) -> image::ImageResult<Self> {
+        let data = image::open(filename)?.to_rgb8();
         // let width = data.width() as usize;
         // let height = data.height() as usize;
         let (width, height) = data.dimensions();
-        let bytes_per_scanline = ImageTexture::BYTES_PER_PIXEL * width as usize;
 
-        Self {
+        Ok(Self {
             data,
             width: width as usize,
             height: height as usize,
-            bytes_per_scanline,
-        }
+        })
     }
 }
 
@@ -0,0 +1,157 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The order in which tiles are handed out to render threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row from the top left, like the image is stored.
+    Scanline,
+    /// Outwards from the centre of the image, so the subject shows up first.
+    #[default]
+    Spiral,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            _ => Err(format!("unknown tile order \"{}\"", s)),
+        }
+    }
+}
+
+/// A rectangle of pixels `x0..x1` by `y0..y1`, with rows counted from the
+/// top of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn pixels(&self) -> usize {
+        self.width() * self.height()
+    }
+}
+
+/// Cuts a `width` by `height` image into tiles of at most `tile_size`
+/// pixels square, listed in the given order.
+pub fn tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let tile_size: usize = tile_size.max(1);
+    let nx: usize = width.div_ceil(tile_size);
+    let ny: usize = height.div_ceil(tile_size);
+    let mut cells: Vec<(usize, usize)> = (0..ny)
+        .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+        .collect();
+
+    if order == TileOrder::Spiral {
+        // Walk rings of tiles around the centre, each ring clockwise from
+        // straight up
+        let cx: f64 = (nx as f64 - 1.0) / 2.0;
+        let cy: f64 = (ny as f64 - 1.0) / 2.0;
+        let key = |&(tx, ty): &(usize, usize)| {
+            let dx: f64 = tx as f64 - cx;
+            let dy: f64 = ty as f64 - cy;
+            let ring: f64 = dx.abs().max(dy.abs()).round();
+            let angle: f64 = dx.atan2(-dy).rem_euclid(std::f64::consts::TAU);
+            (ring, angle)
+        };
+        cells.sort_by(|a, b| {
+            let (ra, aa) = key(a);
+            let (rb, ab) = key(b);
+            ra.total_cmp(&rb).then(aa.total_cmp(&ab))
+        });
+    }
+
+    cells
+        .into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y1: ((ty + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+/// Tiles shared between render threads. Each call to `next` hands out a
+/// tile no other thread gets, so threads keep pulling work until none is
+/// left instead of being given a fixed share up front.
+pub struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+}
+
+impl TileQueue {
+    pub fn new(tiles: Vec<Tile>) -> Self {
+        Self {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn next(&self) -> Option<Tile> {
+        let i: usize = self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(i).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+/// The number of threads to render with when none is given.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_image() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral] {
+            let tiles = tiles(100, 37, 16, order);
+            assert_eq!(tiles.len(), 7 * 3);
+            let mut covered = vec![0; 100 * 37];
+            for tile in &tiles {
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        covered[y * 100 + x] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1));
+        }
+    }
+
+    #[test]
+    fn test_spiral_starts_in_centre() {
+        let tiles = tiles(80, 80, 16, TileOrder::Spiral);
+        assert_eq!((tiles[0].x0, tiles[0].y0), (32, 32));
+        let queue = TileQueue::new(tiles);
+        let mut n = 0;
+        while queue.next().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, queue.len());
+    }
+}
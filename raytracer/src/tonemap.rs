@@ -0,0 +1,194 @@
+use std::str::FromStr;
+
+use crate::color::{luminance, quantize};
+use crate::vec3::Color;
+
+/// Curve compressing scene radiance into the displayable range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Leave values alone and let them clip at 1.
+    Clamp,
+    /// `L / (1 + L)` on luminance, keeping the hue.
+    Reinhard,
+    /// Reinhard scaled so luminance `white` maps to 1.
+    ReinhardExtended { white: f64 },
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+    /// Krzysztof Narkowicz's fit of the ACES reference rendering transform.
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    /// Accepts `clamp`, `reinhard`, `reinhard-extended[:WHITE]`, `hable`
+    /// and `aces`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (s, None),
+        };
+        match (name, arg) {
+            ("clamp", None) => Ok(ToneMap::Clamp),
+            ("reinhard", None) => Ok(ToneMap::Reinhard),
+            ("reinhard-extended", None) => Ok(ToneMap::ReinhardExtended { white: 4.0 }),
+            ("reinhard-extended", Some(w)) => match w.parse::<f64>() {
+                Ok(white) if white > 0.0 => Ok(ToneMap::ReinhardExtended { white }),
+                _ => Err(format!("invalid white point \"{}\"", w)),
+            },
+            ("hable" | "filmic", None) => Ok(ToneMap::Hable),
+            ("aces", None) => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone mapping operator \"{}\"", s)),
+        }
+    }
+}
+
+/// How display values are encoded for the output file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+    /// `sqrt`, the approximation used by "Ray Tracing in One Weekend".
+    Gamma2,
+    /// The piecewise sRGB curve.
+    Srgb,
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gamma2" => Ok(Transfer::Gamma2),
+            "srgb" => Ok(Transfer::Srgb),
+            _ => Err(format!("unknown transfer function \"{}\"", s)),
+        }
+    }
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+}
+
+fn srgb(v: f64) -> f64 {
+    if v <= 0.003_130_8 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn per_channel(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(c.x()), f(c.y()), f(c.z()))
+}
+
+/// Turns linear radiance into display values: scale by the exposure, apply
+/// the tone curve, then encode with the transfer function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapper {
+    /// In stops, so each step of 1 doubles the brightness.
+    pub exposure: f64,
+    pub operator: ToneMap,
+    pub transfer: Transfer,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            operator: ToneMap::Clamp,
+            transfer: Transfer::Gamma2,
+        }
+    }
+}
+
+impl ToneMapper {
+    /// Applies exposure and the tone curve, still in linear space.
+    pub fn tone_map(&self, c: Color) -> Color {
+        let c: Color = c * 2f64.powf(self.exposure);
+        match self.operator {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard | ToneMap::ReinhardExtended { .. } => {
+                let l: f64 = luminance(c);
+                if l <= 0.0 {
+                    return Color::new(0.0, 0.0, 0.0);
+                }
+                let white2: f64 = match self.operator {
+                    ToneMap::ReinhardExtended { white } => white * white,
+                    _ => f64::INFINITY,
+                };
+                let ld: f64 = l * (1.0 + l / white2) / (1.0 + l);
+                c * (ld / l)
+            }
+            ToneMap::Hable => {
+                let exposure_bias: f64 = 2.0;
+                let white_scale: f64 = 1.0 / hable_partial(11.2);
+                per_channel(c, |v| hable_partial(v * exposure_bias) * white_scale)
+            }
+            ToneMap::Aces => per_channel(c, aces),
+        }
+    }
+
+    /// The encoded display value of `c`, each channel in [0,1].
+    pub fn map(&self, c: Color) -> Color {
+        let c: Color = per_channel(self.tone_map(c), |v| v.max(0.0));
+        match self.transfer {
+            Transfer::Gamma2 => per_channel(c, f64::sqrt),
+            Transfer::Srgb => per_channel(c, |v| srgb(v.min(1.0))),
+        }
+    }
+
+    pub fn to_rgb8(&self, c: Color) -> [u8; 3] {
+        quantize(self.map(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_gamma_2() {
+        let tm = ToneMapper::default();
+        assert_eq!(tm.to_rgb8(Color::new(0.25, 1.0, 15.0)), [127, 254, 254]);
+    }
+
+    #[test]
+    fn test_operators_compress_highlights() {
+        let bright = Color::new(15.0, 15.0, 15.0);
+        for operator in ["reinhard", "reinhard-extended:20", "hable", "aces"] {
+            let tm = ToneMapper {
+                operator: operator.parse().unwrap(),
+                transfer: Transfer::Srgb,
+                ..ToneMapper::default()
+            };
+            let lit = tm.map(bright).x();
+            let dim = tm.map(Color::new(0.5, 0.5, 0.5)).x();
+            assert!(lit < 1.0 && dim < lit, "{}", operator);
+            assert_eq!(tm.map(Color::new(0.0, 0.0, 0.0)).x(), 0.0);
+        }
+        // The extended curve reaches 1 at its white point
+        let tm = ToneMapper {
+            operator: ToneMap::ReinhardExtended { white: 4.0 },
+            ..ToneMapper::default()
+        };
+        assert!((tm.tone_map(Color::new(4.0, 4.0, 4.0)).x() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_exposure_and_srgb() {
+        let tm = ToneMapper {
+            exposure: 1.0,
+            operator: ToneMap::Clamp,
+            transfer: Transfer::Srgb,
+        };
+        assert!((tm.tone_map(Color::new(0.25, 0.0, 0.0)).x() - 0.5).abs() < 1e-12);
+        assert!((srgb(0.5) - 0.735_356_983).abs() < 1e-6);
+        assert!("unknown".parse::<ToneMap>().is_err());
+        assert!("reinhard-extended:0".parse::<ToneMap>().is_err());
+    }
+}
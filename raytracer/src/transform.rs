@@ -0,0 +1,619 @@
+use std::ops::Mul;
+
+use crate::aabb::Aabb;
+use crate::animation::{Keyframes, Lerp};
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// A 4x4 matrix acting on column vectors, `m[row][column]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        let mut m: [[f64; 4]; 4] = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m: [[f64; 4]; 4] = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    /// The inverse by Gauss-Jordan elimination with partial pivoting, or
+    /// None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a: [[f64; 4]; 4] = self.m;
+        let mut inv: [[f64; 4]; 4] = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot: usize = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale: f64 = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor: f64 = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[col][k];
+                        inv[row][k] -= factor * inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    /// Applies the matrix to `v` extended by `w`, dropping the fourth
+    /// coordinate of the result. Affine matrices leave it at `w`.
+    fn apply(&self, v: Vec3, w: f64) -> Vec3 {
+        let row = |r: &[f64; 4]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z() + r[3] * w;
+        Vec3::new(row(&self.m[0]), row(&self.m[1]), row(&self.m[2]))
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m: [[f64; 4]; 4] = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+/// An affine transform from object to world space, kept together with its
+/// inverse. `a * b` applies `b` first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    /// A transform by an affine `matrix`, or None if it cannot be undone.
+    pub fn new(matrix: Mat4) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn identity() -> Self {
+        Self {
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut matrix: Mat4 = Mat4::identity();
+        let mut inverse: Mat4 = Mat4::identity();
+        for i in 0..3 {
+            matrix.m[i][3] = offset[i as i32];
+            inverse.m[i][3] = -offset[i as i32];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Scales by a factor per axis, none of them 0.
+    pub fn scale(factors: Vec3) -> Self {
+        let mut matrix: Mat4 = Mat4::identity();
+        let mut inverse: Mat4 = Mat4::identity();
+        for i in 0..3 {
+            matrix.m[i][i] = factors[i as i32];
+            inverse.m[i][i] = 1.0 / factors[i as i32];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Rotates counterclockwise by `degrees` looking down `axis` towards
+    /// the origin.
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let a: Vec3 = Vec3::unit_vector(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let t: f64 = 1.0 - cos;
+        let matrix = Mat4::new([
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.0,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.0,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // Rotations are orthogonal
+        Self {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Transform::rotate(Vec3::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.apply(p, 1.0)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.apply(v, 0.0)
+    }
+
+    /// How many times more solid angle the directions around `v` cover
+    /// before this transform than after it, so a density over directions
+    /// before becomes one over directions after when multiplied by it. It
+    /// is 1 for rotations and even scales, which keep angles.
+    pub fn solid_angle_ratio(&self, v: Vec3) -> f64 {
+        let m: &[[f64; 4]; 4] = &self.inverse.m;
+        let determinant: f64 = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        let w: Vec3 = self.inverse.apply(Vec3::unit_vector(v), 0.0);
+        determinant.abs() / w.length().powi(3)
+    }
+
+    /// Transforms a surface normal by the inverse transpose, so it stays
+    /// perpendicular to the transformed surface. The result is not unit
+    /// length.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let m: &[[f64; 4]; 4] = &self.inverse.m;
+        let column = |j: usize| m[0][j] * n.x() + m[1][j] * n.y() + m[2][j] * n.z();
+        Vec3::new(column(0), column(1), column(2))
+    }
+
+    /// The ray in the space this transform maps from. Its direction is not
+    /// renormalized, so distances `t` along it are the same as along `r`.
+    pub fn inverse_ray(&self, r: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.apply(r.orig, 1.0),
+            self.inverse.apply(r.dir, 0.0),
+            r.tm,
+        )
+    }
+
+    /// The smallest box around the transformed `box_` (Arvo 1990): every
+    /// output axis takes the smaller and larger product of each matrix
+    /// entry with the input extents.
+    pub fn aabb(&self, box_: &Aabb) -> Aabb {
+        let mut min: [f64; 3] = [0.0; 3];
+        let mut max: [f64; 3] = [0.0; 3];
+        for i in 0..3 {
+            min[i] = self.matrix.m[i][3];
+            max[i] = self.matrix.m[i][3];
+            for j in 0..3 {
+                let a: f64 = self.matrix.m[i][j] * box_.min()[j as i32];
+                let b: f64 = self.matrix.m[i][j] * box_.max()[j as i32];
+                min[i] += a.min(b);
+                max[i] += a.max(b);
+            }
+        }
+        Aabb::new(
+            Point3::new(min[0], min[1], min[2]),
+            Point3::new(max[0], max[1], max[2]),
+        )
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+/// A rotation as a unit quaternion `w + xi + yj + zk`, which unlike a
+/// matrix interpolates smoothly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            v: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Rotates by `degrees` about `axis`, the same way as
+    /// `Transform::rotate`.
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let (sin, cos) = (0.5 * degrees.to_radians()).sin_cos();
+        Self {
+            w: cos,
+            v: sin * Vec3::unit_vector(axis),
+        }
+    }
+
+    fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + Vec3::dot(self.v, other.v)
+    }
+
+    fn normalized(&self) -> Self {
+        let length: f64 = self.dot(self).sqrt();
+        Self {
+            w: self.w / length,
+            v: self.v / length,
+        }
+    }
+
+    /// Spherical interpolation from `self` at `s` = 0 to `other` at 1, at
+    /// constant angular speed and the shorter way round.
+    pub fn slerp(&self, other: &Quat, s: f64) -> Self {
+        let mut cos: f64 = self.dot(other);
+        let mut end: Quat = *other;
+        if cos < 0.0 {
+            cos = -cos;
+            end = Quat {
+                w: -end.w,
+                v: -end.v,
+            };
+        }
+        // Nearly the same rotation, where the angle is too small to divide by
+        if cos > 0.9995 {
+            return Quat {
+                w: self.w + s * (end.w - self.w),
+                v: self.v + s * (end.v - self.v),
+            }
+            .normalized();
+        }
+        let theta: f64 = cos.acos();
+        let a: f64 = ((1.0 - s) * theta).sin() / theta.sin();
+        let b: f64 = (s * theta).sin() / theta.sin();
+        Quat {
+            w: a * self.w + b * end.w,
+            v: a * self.v + b * end.v,
+        }
+    }
+
+    /// The angle in radians the rotation from `self` to `other` turns by.
+    pub fn angle_to(&self, other: &Quat) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    pub fn transform(&self) -> Transform {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        let matrix = Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+}
+
+/// A transform taken apart into a scale, then a rotation, then a move,
+/// the parts that interpolate on their own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    /// Factors per axis, none of them 0.
+    pub scale: Vec3,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quat::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        Transform::translate(self.translation)
+            * self.rotation.transform()
+            * Transform::scale(self.scale)
+    }
+}
+
+impl Lerp for Quat {
+    fn lerp(&self, other: &Quat, s: f64) -> Quat {
+        self.slerp(other, s)
+    }
+}
+
+/// Moves and scales linearly and rotates by `Quat::slerp`.
+impl Lerp for Pose {
+    fn lerp(&self, other: &Pose, s: f64) -> Pose {
+        Pose {
+            translation: self.translation.lerp(&other.translation, s),
+            rotation: self.rotation.slerp(&other.rotation, s),
+            scale: self.scale.lerp(&other.scale, s),
+        }
+    }
+}
+
+/// A transform following keyframed poses, holding still before the first
+/// and after the last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimatedTransform {
+    pub keys: Keyframes<Pose>,
+}
+
+impl AnimatedTransform {
+    /// Goes from `start` at `time0` to `end` at `time1`.
+    pub fn new(start: Pose, end: Pose, time0: f64, time1: f64) -> Self {
+        Self {
+            keys: Keyframes::new(vec![(time0, start), (time1, end)]).unwrap(),
+        }
+    }
+
+    pub fn from_keys(keys: Keyframes<Pose>) -> Self {
+        Self { keys }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        self.keys.at(time).transform()
+    }
+
+    /// A box around `box_` wherever the motion takes it from `time0` to
+    /// `time1`, swept one keyframe to the next.
+    pub fn aabb(&self, box_: &Aabb, time0: f64, time1: f64) -> Aabb {
+        let mut times: Vec<f64> = vec![time0];
+        times.extend(
+            self.keys
+                .keys()
+                .iter()
+                .map(|&(t, _)| t)
+                .filter(|&t| t > time0 && t < time1),
+        );
+        if time1 > time0 {
+            times.push(time1);
+        }
+        let mut bounds: Aabb = self.at(time0).aabb(box_);
+        for pair in times.windows(2) {
+            let swept: Aabb = sweep(&self.keys.at(pair[0]), &self.keys.at(pair[1]), box_);
+            bounds = Aabb::surrounding_box(bounds, swept);
+        }
+        bounds
+    }
+}
+
+/// A box around `box_` going from `start` to `end`. Moving and scaling
+/// alone keep the corners on straight lines, so the poses at both ends
+/// would do; rotations swing them along arcs, which are sampled in small
+/// steps with the bulge of an arc between two steps added all round.
+fn sweep(start: &Pose, end: &Pose, box_: &Aabb) -> Aabb {
+    let angle: f64 = start.rotation.angle_to(&end.rotation);
+    let steps: usize = if angle > 0.0 {
+        ((angle / 2.0_f64.to_radians()).ceil() as usize).max(1)
+    } else {
+        1
+    };
+    let mut bounds: Aabb = start.transform().aabb(box_);
+    for i in 1..=steps {
+        let pose: Pose = start.lerp(end, i as f64 / steps as f64);
+        bounds = Aabb::surrounding_box(bounds, pose.transform().aabb(box_));
+    }
+    if angle > 0.0 {
+        // The farthest any scaled corner gets from the rotation center
+        let mut radius: f64 = 0.0;
+        for pose in [start, end] {
+            let far = |i: i32| box_.min()[i].abs().max(box_.max()[i].abs()) * pose.scale[i].abs();
+            radius = radius.max(Vec3::new(far(0), far(1), far(2)).length());
+        }
+        let step: f64 = angle / steps as f64;
+        let bulge: f64 = radius * (1.0 - (0.5 * step).cos());
+        let pad = Vec3::new(bulge, bulge, bulge);
+        bounds = Aabb::new(bounds.min() - pad, bounds.max() + pad);
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let t = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate_y(90.0)
+            * Transform::scale(Vec3::new(2.0, 1.0, 1.0));
+        // Scaled first, then rotated, then moved
+        let p = t.point(Point3::new(1.0, 0.0, 0.0));
+        assert_near(p, Point3::new(1.0, 2.0, 1.0));
+        assert_near(t.inverse().point(p), Point3::new(1.0, 0.0, 0.0));
+        assert_near(
+            t.vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -2.0),
+        );
+
+        let general = Transform::new(t.matrix).unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((general.inverse.m[i][j] - t.inverse.m[i][j]).abs() < 1e-12);
+            }
+        }
+        let flat = Mat4::new([[1.0, 0.0, 0.0, 0.0], [0.0; 4], [0.0; 4], [0.0; 4]]);
+        assert!(Transform::new(flat).is_none());
+    }
+
+    #[test]
+    fn test_rotations() {
+        assert_near(
+            Transform::rotate_x(90.0).vector(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        assert_near(
+            Transform::rotate_z(90.0).vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let axis = Vec3::new(1.0, 1.0, 1.0);
+        let t = Transform::rotate(axis, 120.0);
+        assert_near(t.vector(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0));
+        assert_near(t.vector(axis), axis);
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular() {
+        let t = Transform::rotate_z(30.0) * Transform::scale(Vec3::new(4.0, 1.0, 1.0));
+        // The plane x + y = 0 and its normal
+        let tangent = t.vector(Vec3::new(1.0, -1.0, 0.0));
+        let normal = t.normal(Vec3::new(1.0, 1.0, 0.0));
+        assert!(Vec3::dot(tangent, normal).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aabb() {
+        let box_ = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let t = Transform::translate(Vec3::new(5.0, 0.0, 0.0)) * Transform::rotate_y(45.0);
+        let out = t.aabb(&box_);
+        let r: f64 = 2.0_f64.sqrt();
+        assert_near(out.min(), Point3::new(5.0 - r, -1.0, -r));
+        assert_near(out.max(), Point3::new(5.0 + r, 1.0, r));
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        assert_near(b.transform().vector(x), Transform::rotate_y(90.0).vector(x));
+        let half = a.slerp(&b, 0.5).transform().vector(x);
+        assert_near(half, Transform::rotate_y(45.0).vector(x));
+        // 270 degrees one way is 90 the other
+        let c = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 270.0);
+        assert_near(
+            a.slerp(&c, 0.5).transform().vector(x),
+            Transform::rotate_y(-45.0).vector(x),
+        );
+        assert_eq!(a.slerp(&a, 0.3), a);
+    }
+
+    #[test]
+    fn test_animated_transform() {
+        let start = Pose {
+            translation: Vec3::new(4.0, 0.0, 0.0),
+            ..Pose::default()
+        };
+        let end = Pose {
+            translation: Vec3::new(4.0, 2.0, 0.0),
+            rotation: Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 180.0),
+            scale: Vec3::new(2.0, 2.0, 2.0),
+        };
+        let motion = AnimatedTransform::new(start, end, 1.0, 3.0);
+        let p = Point3::new(1.0, 0.0, 0.0);
+        assert_near(motion.at(0.0).point(p), Point3::new(5.0, 0.0, 0.0));
+        assert_near(motion.at(2.0).point(p), Point3::new(4.0, 2.5, 0.0));
+        assert_near(motion.at(5.0).point(p), Point3::new(2.0, 2.0, 0.0));
+
+        // The box holds every corner at every time, including halfway
+        // through the turn where neither end pose reaches
+        let box_ = Aabb::new(Point3::new(0.0, -0.5, -0.5), Point3::new(1.0, 0.5, 0.5));
+        let bounds = motion.aabb(&box_, 1.0, 3.0);
+        for i in 0..=1000 {
+            let t: Transform = motion.at(1.0 + 2.0 * i as f64 / 1000.0);
+            for x in [0.0, 1.0] {
+                for y in [-0.5, 0.5] {
+                    for z in [-0.5, 0.5] {
+                        let q = t.point(Point3::new(x, y, z));
+                        for axis in 0..3 {
+                            assert!(q[axis] >= bounds.min()[axis] - 1e-9);
+                            assert!(q[axis] <= bounds.max()[axis] + 1e-9);
+                        }
+                    }
+                }
+            }
+        }
+        assert!(bounds.max().y() > 2.0);
+        // Before the motion starts the box stays where it is
+        let still = motion.aabb(&box_, 0.0, 1.0);
+        assert_near(still.min(), Point3::new(4.0, -0.5, -0.5));
+        assert_near(still.max(), Point3::new(5.0, 0.5, 0.5));
+    }
+}
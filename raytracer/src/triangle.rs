@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::random::{self, SampleRng};
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// Texture coordinates used when a triangle has none of its own.
+pub const DEFAULT_UVS: [(f64, f64); 3] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)];
+
+/// Möller–Trumbore ray/triangle intersection. Returns `t` and the
+/// barycentric coordinates of the hit with respect to `p[1]` and `p[2]`.
+pub fn intersect(p: &[Point3; 3], r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge1: Vec3 = p[1] - p[0];
+    let edge2: Vec3 = p[2] - p[0];
+    let pvec: Vec3 = Vec3::cross(r.dir, edge2);
+    let det: f64 = Vec3::dot(edge1, pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det: f64 = 1.0 / det;
+
+    let tvec: Vec3 = r.orig - p[0];
+    let b1: f64 = Vec3::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec: Vec3 = Vec3::cross(tvec, edge1);
+    let b2: f64 = Vec3::dot(r.dir, qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t: f64 = Vec3::dot(edge2, qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Builds the hit record of a triangle from its barycentric hit coordinates,
+/// interpolating shading normals and texture coordinates.
+#[allow(clippy::too_many_arguments)]
+pub fn hit_record<'a>(
+    p: &[Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: &[(f64, f64); 3],
+    mat_ptr: &'a dyn Material,
+    r: &Ray,
+    t: f64,
+    b1: f64,
+    b2: f64,
+) -> HitRecord<'a> {
+    let b0: f64 = 1.0 - b1 - b2;
+    let geometric: Vec3 = Vec3::unit_vector(Vec3::cross(p[1] - p[0], p[2] - p[0]));
+    let outward_normal: Vec3 = match normals {
+        Some(n) => {
+            // Shading normals are kept on the same side as the geometry
+            let shading: Vec3 = Vec3::unit_vector(b0 * n[0] + b1 * n[1] + b2 * n[2]);
+            if Vec3::dot(shading, geometric) < 0.0 {
+                -shading
+            } else {
+                shading
+            }
+        }
+        None => geometric,
+    };
+    let u: f64 = b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0;
+    let v: f64 = b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1;
+    HitRecord::new(r.at(t), t, u, v, mat_ptr, outward_normal, *r)
+}
+
+/// The bounding box of three points, padded so it never has zero thickness.
+pub fn bounding_box(p: &[Point3; 3]) -> Aabb {
+    let pad: f64 = 0.0001;
+    let min: Point3 = Point3::new(
+        p[0].x().min(p[1].x()).min(p[2].x()) - pad,
+        p[0].y().min(p[1].y()).min(p[2].y()) - pad,
+        p[0].z().min(p[1].z()).min(p[2].z()) - pad,
+    );
+    let max: Point3 = Point3::new(
+        p[0].x().max(p[1].x()).max(p[2].x()) + pad,
+        p[0].y().max(p[1].y()).max(p[2].y()) + pad,
+        p[0].z().max(p[1].z()).max(p[2].z()) + pad,
+    );
+    Aabb::new(min, max)
+}
+
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: [(f64, f64); 3],
+    pub mp: Arc<dyn Material + Send + Sync>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, mat: Arc<dyn Material + Send + Sync>) -> Self {
+        Self {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: DEFAULT_UVS,
+            mp: mat,
+        }
+    }
+
+    pub fn new_with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: [(f64, f64); 3],
+        mat: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        Self {
+            vertices,
+            normals,
+            uvs,
+            mp: mat,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        let p = &self.vertices;
+        0.5 * Vec3::cross(p[1] - p[0], p[2] - p[0]).length()
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect(&self.vertices, r, t_min, t_max)?;
+        Some(hit_record(
+            &self.vertices,
+            self.normals,
+            &self.uvs,
+            &*self.mp,
+            r,
+            t,
+            b1,
+            b2,
+        ))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        Some(bounding_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3, time: f64) -> f64 {
+        if let Some((t, _, _)) = intersect(
+            &self.vertices,
+            &Ray::new(*o, *v, time),
+            0.001,
+            f64::INFINITY,
+        ) {
+            let p = &self.vertices;
+            let normal: Vec3 = Vec3::unit_vector(Vec3::cross(p[1] - p[0], p[2] - p[0]));
+            let distance_squared: f64 = t * t * v.squared_length();
+            let cosine: f64 = (Vec3::dot(*v, normal) / v.length()).abs();
+            distance_squared / (cosine * self.area())
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, o: &Point3, _time: f64) -> Vec3 {
+        let mut rng: SampleRng = random::rng();
+        let r1: f64 = rng.gen::<f64>().sqrt();
+        let r2: f64 = rng.gen();
+        let p = &self.vertices;
+        let random_point: Point3 = (1.0 - r1) * p[0] + r1 * (1.0 - r2) * p[1] + r1 * r2 * p[2];
+        random_point - *o
+    }
+}
@@ -1,3 +1,5 @@
+use crate::random::{self, SampleRng};
+use crate::sampler;
 use rand::Rng;
 use std::ops::Index;
 use std::ops::Neg;
@@ -60,7 +62,7 @@ impl Vec3 {
     }
 
     pub fn random_in_unit_sphere() -> Self {
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
+        let mut rng: SampleRng = random::rng();
         loop {
             let r: f64 = rng.gen_range(-1.0..1.0);
             let g: f64 = rng.gen_range(-1.0..1.0);
@@ -74,8 +76,11 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector() -> Self {
-        let r: Vec3 = Vec3::random_in_unit_sphere();
-        Vec3::unit_vector(r)
+        let (r1, r2) = sampler::get_2d();
+        let z: f64 = 1.0 - 2.0 * r2;
+        let r: f64 = (1.0 - z * z).max(0.0).sqrt();
+        let phi: f64 = 2.0 * std::f64::consts::PI * r1;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
     pub fn random_in_hemisphere(normal: &Vec3) -> Self {
@@ -87,17 +92,44 @@ impl Vec3 {
         }
     }
 
+    /// Shirley and Chiu's concentric mapping of the square onto the disk,
+    /// which keeps the sampler's strata intact unlike rejection sampling.
     pub fn random_in_unit_disk() -> Self {
-        let mut rng: rand::rngs::ThreadRng = rand::thread_rng();
-        loop {
-            let r: f64 = rng.gen_range(-1.0..1.0);
-            let g: f64 = rng.gen_range(-1.0..1.0);
-            let p: Vec3 = Vec3::new(r, g, 0.0);
-            if p.squared_length() >= 1.0 {
-                continue;
-            }
-            return p;
+        let (r1, r2) = sampler::get_2d();
+        let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, std::f64::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (
+                b,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+            )
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    pub fn random_cosine_direction() -> Self {
+        let (r1, r2) = sampler::get_2d();
+        let z: f64 = (1.0 - r2).sqrt();
+
+        let phi: f64 = 2.0 * std::f64::consts::PI * r1;
+        let x: f64 = phi.cos() * r2.sqrt();
+        let y: f64 = phi.sin() * r2.sqrt();
+        Vec3::new(x, y, z)
+    }
+
+    /// A direction towards a sphere of `radius` seen from `distance_squared` away, around +z.
+    pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Self {
+        let (r1, r2) = sampler::get_2d();
+        let z: f64 = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).max(0.0).sqrt() - 1.0);
+
+        let phi: f64 = 2.0 * std::f64::consts::PI * r1;
+        let x: f64 = phi.cos() * (1.0 - z * z).sqrt();
+        let y: f64 = phi.sin() * (1.0 - z * z).sqrt();
+        Vec3::new(x, y, z)
     }
 
     pub fn near_zero(&self) -> bool {
@@ -0,0 +1,344 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use raytracer::aarect::XzRect;
+use raytracer::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use raytracer::render::MisHeuristic;
+use raytracer::sphere::Sphere;
+use raytracer::{
+    AdaptiveSampling, Aov, BVHNode, Camera, Color, Filter, HittableList, Integrator, Point3,
+    Renderer, Scene, Vec3,
+};
+
+#[test]
+fn test_render_sphere() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        1.0,
+        Lambertian::new(Color::new(0.0, 0.0, 0.0)),
+    )));
+    let cam = Camera::new(
+        Point3::new(0.0, 0.0, -5.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let mut renderer = Renderer::new(16, 16);
+    renderer.samples_per_pixel = 4;
+    renderer.threads = 2;
+    renderer.background = Color::new(1.0, 1.0, 1.0);
+
+    let world_copy = world.clone();
+    let img = renderer.render(BVHNode::new_boxed(world, 0.0, 1.0), &cam);
+    assert_eq!(img.dimensions(), (16, 16));
+    // The black sphere covers the centre, the white sky the corners
+    assert_eq!(img.get_pixel(8, 8).0, [0, 0, 0]);
+    assert_eq!(img.get_pixel(0, 0).0, [254, 254, 254]);
+
+    // A single pixel averages the whole view, sphere and sky
+    let mut renderer = Renderer::new(1, 1);
+    renderer.samples_per_pixel = 4;
+    renderer.background = Color::new(1.0, 1.0, 1.0);
+    let fb = renderer.render_framebuffer(BVHNode::new_boxed(world_copy, 0.0, 1.0), &cam);
+    let c: f64 = fb.pixel(0, 0).x();
+    assert!(c.is_finite() && c < 1.0);
+}
+
+#[test]
+fn test_load_example_scene() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenes/cornell_box.toml");
+    let scene = Scene::load(&path).unwrap();
+    assert_eq!(scene.world.hittable_list.len(), 8);
+    assert_eq!((scene.width, scene.height), (600, 600));
+}
+
+#[test]
+fn test_integrators_agree() {
+    let white = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let light = Arc::new(DiffuseLight::new_col(Color::new(4.0, 4.0, 4.0)));
+    let mut world = HittableList::new();
+    world.add(Arc::new(XzRect::new(-10.0, 10.0, -10.0, 10.0, 0.0, white)));
+    world.add(Arc::new(XzRect::new(
+        -1.0,
+        1.0,
+        -1.0,
+        1.0,
+        3.0,
+        light.clone(),
+    )));
+    let mut lights = HittableList::new();
+    lights.add(Arc::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 3.0, light)));
+    let cam = Camera::new(
+        Point3::new(0.0, 2.0, -4.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        30.0,
+        1.0,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let world = BVHNode::new_boxed(world, 0.0, 1.0);
+
+    let mut renderer = Renderer::new(16, 16);
+    renderer.samples_per_pixel = 64;
+    renderer.threads = 4;
+    renderer.lights = lights;
+    let mean = |integrator| {
+        let mut renderer = renderer.clone();
+        renderer.integrator = integrator;
+        renderer.features = true;
+        let fb = renderer.render_framebuffer(world.clone(), &cam);
+        // Light only reaches the camera off the floor, after one bounce
+        let [emission, direct, indirect] =
+            [Aov::Emission, Aov::Direct, Aov::Indirect].map(|aov| aov.values(&fb));
+        for i in 0..16 * 16 {
+            assert_eq!(emission[i], Color::new(0.0, 0.0, 0.0));
+            assert_eq!(indirect[i], Color::new(0.0, 0.0, 0.0));
+            assert!((direct[i] - fb.pixel(i % 16, i / 16)).length() < 1e-9);
+        }
+        assert!(fb.hits.iter().all(|&n| n == 64));
+        let img = fb.to_rgb8();
+        img.pixels().map(|p| p.0[0] as f64).sum::<f64>() / (16.0 * 16.0)
+    };
+    let recursive = mean(Integrator::Recursive);
+    let mis = mean(Integrator::Mis(MisHeuristic::Power));
+    assert!(recursive > 10.0);
+    assert!((recursive - mis).abs() < 0.05 * recursive);
+}
+
+#[test]
+fn test_integrators_agree_on_fuzzy_metal() {
+    let light = Arc::new(DiffuseLight::new_col(Color::new(4.0, 4.0, 4.0)));
+    let mut world = HittableList::new();
+    world.add(Arc::new(XzRect::new(
+        -10.0,
+        10.0,
+        -10.0,
+        10.0,
+        0.0,
+        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.3)),
+    )));
+    world.add(Arc::new(XzRect::new(
+        -1.0,
+        1.0,
+        -1.0,
+        1.0,
+        3.0,
+        light.clone(),
+    )));
+    let mut lights = HittableList::new();
+    lights.add(Arc::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 3.0, light)));
+    // Looking at where the floor mirrors the light, so the glossy lobe and
+    // the light overlap
+    let cam = Camera::new(
+        Point3::new(0.0, 2.0, -4.0),
+        Point3::new(0.0, 0.0, -2.4),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let world = BVHNode::new_boxed(world, 0.0, 1.0);
+
+    let mut renderer = Renderer::new(16, 16);
+    renderer.samples_per_pixel = 256;
+    renderer.threads = 4;
+    renderer.lights = lights;
+    let mean = |integrator| {
+        let mut renderer = renderer.clone();
+        renderer.integrator = integrator;
+        let fb = renderer.render_framebuffer(world.clone(), &cam);
+        let mut sum: f64 = 0.0;
+        for y in 0..16 {
+            for x in 0..16 {
+                let c = fb.pixel(x, y);
+                assert!(c.x().is_finite() && c.y().is_finite() && c.z().is_finite());
+                sum += c.x();
+            }
+        }
+        sum / (16.0 * 16.0)
+    };
+    let recursive = mean(Integrator::Recursive);
+    let mis = mean(Integrator::Mis(MisHeuristic::Power));
+    assert!(recursive > 0.1);
+    assert!((recursive - mis).abs() < 0.05 * recursive);
+}
+
+#[test]
+fn test_progressive_passes() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        1.0,
+        Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+    )));
+    let cam = Camera::new(
+        Point3::new(0.0, 0.0, -5.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.5,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let mut renderer = Renderer::new(12, 8);
+    renderer.samples_per_pixel = 5;
+    renderer.threads = 2;
+    renderer.background = Color::new(0.7, 0.8, 1.0);
+    let world = BVHNode::new_boxed(world, 0.0, 1.0);
+
+    let mut seen = vec![];
+    let (fb, progress) = renderer.render_progressive(world.clone(), &cam, 2, |progress, fb| {
+        seen.push((progress.passes, fb.weights[0]));
+        true
+    });
+    // Two full passes and a last one with the remaining sample
+    assert_eq!(seen, vec![(1, 2.0), (2, 4.0), (3, 5.0)]);
+    assert!(fb.weights.iter().all(|&w| w == 5.0));
+    assert_eq!(progress.samples, 5);
+
+    let (fb, progress) = renderer.render_progressive(world.clone(), &cam, 2, |_, _| false);
+    assert!(fb.weights.iter().all(|&w| w == 2.0));
+
+    // Resuming only takes the samples still missing
+    let (fb, _) = renderer.resume_progressive(world.clone(), &cam, 2, fb, progress, |_, _| true);
+    assert!(fb.samples.iter().all(|&n| n == 5));
+
+    // Resuming a finished render still hands over its image
+    let mut passes: usize = 0;
+    let (fb, _) = renderer.resume_progressive(world.clone(), &cam, 2, fb, progress, |_, _| {
+        passes += 1;
+        true
+    });
+    assert_eq!(passes, 1);
+    assert!(fb.samples.iter().all(|&n| n == 5));
+
+    // A stopped render keeps only whole passes
+    renderer
+        .stop
+        .store(true, std::sync::atomic::Ordering::Relaxed);
+    let (fb, progress) = renderer.render_progressive(world, &cam, 2, |_, _| true);
+    assert_eq!(progress, Default::default());
+    assert!(fb.weights.iter().all(|&w| w == 0.0));
+}
+
+#[test]
+fn test_seed_is_reproducible() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        1.0,
+        Dielectric::new(1.5),
+    )));
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -101.0, 0.0),
+        100.0,
+        Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+    )));
+    // A lens aperture and shutter interval so the camera draws samples too
+    let cam = Camera::new(
+        Point3::new(0.0, 1.0, -5.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.5,
+        0.2,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let world = BVHNode::new_boxed(world, 0.0, 1.0);
+    let render_filtered =
+        |threads: usize, tile_size: usize, pass_samples: u64, seed: u64, filter: Filter| {
+            let mut renderer = Renderer::new(24, 16);
+            renderer.filter = filter;
+            renderer.samples_per_pixel = 6;
+            renderer.background = Color::new(0.7, 0.8, 1.0);
+            renderer.threads = threads;
+            renderer.tile_size = tile_size;
+            renderer.seed = seed;
+            renderer
+                .render_progressive(world.clone(), &cam, pass_samples, |_, _| true)
+                .0
+        };
+    let render = |threads: usize, tile_size: usize, pass_samples: u64, seed: u64| {
+        render_filtered(threads, tile_size, pass_samples, seed, Filter::default())
+    };
+
+    let reference = render(1, 16, 6, 42);
+    assert_eq!(render(4, 5, 6, 42), reference);
+    // Splitting into passes only changes the order sums are added in
+    let passes = render(3, 8, 2, 42);
+    assert_eq!(passes.samples, reference.samples);
+    for (a, b) in passes.sums.iter().zip(&reference.sums) {
+        assert!((*a - *b).length() < 1e-9);
+    }
+    assert_ne!(render(1, 16, 6, 43), reference);
+
+    // Overlapping filter footprints are merged in a fixed order
+    let mitchell = Filter::Mitchell { radius: 2.0 };
+    assert_eq!(
+        render_filtered(1, 8, 6, 42, mitchell),
+        render_filtered(4, 8, 6, 42, mitchell)
+    );
+}
+
+#[test]
+fn test_adaptive_sampling() {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, 0.0, 0.0),
+        1.0,
+        Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+    )));
+    // Light the sphere partly off the ground so it shows noise
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.0, -101.0, 0.0),
+        100.0,
+        Lambertian::new(Color::new(0.5, 0.5, 0.5)),
+    )));
+    let cam = Camera::new(
+        Point3::new(0.0, 0.0, -5.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        30.0,
+        1.0,
+        0.0,
+        5.0,
+        0.0,
+        1.0,
+    );
+    let mut renderer = Renderer::new(16, 16);
+    renderer.samples_per_pixel = 32;
+    renderer.threads = 2;
+    renderer.background = Color::new(0.7, 0.8, 1.0);
+    renderer.adaptive = Some(AdaptiveSampling {
+        threshold: 0.01,
+        min_samples: 8,
+        max_samples: 256,
+    });
+    let world = BVHNode::new_boxed(world, 0.0, 1.0);
+
+    let (fb, progress) = renderer.render_progressive(world, &cam, 8, |_, _| true);
+    // The plain sky converges at once, the sphere takes what it saves
+    let sky = fb.samples[0];
+    let sphere = fb.samples[8 * 16 + 8];
+    assert_eq!(sky, 8);
+    assert!(sphere > 32, "{} samples on the sphere", sphere);
+    let total: u64 = fb.samples.iter().map(|&n| n as u64).sum();
+    assert!(total <= 32 * 16 * 16 + 16 * 16);
+    assert_eq!(progress.samples, total / (16 * 16));
+}